@@ -1,7 +1,10 @@
-use std::io::Write;
+use std::fs::File;
+use std::io::{BufReader, Write};
 
 use clap::Parser;
-use txoutset::{ComputeAddresses, Dump};
+use txoutset::descriptor::ScanObject;
+use txoutset::pattern::{Pattern, Search};
+use txoutset::{top, Aggregator, ComputeAddresses, Dump, DumpInfo, Headers, TxOut};
 
 /// Parse the UTXO set dump file and output each entry as CSV
 ///
@@ -26,19 +29,47 @@ struct Args {
     /// Check that the file exists and print simple metadata about the snapshot
     #[arg(short, long, default_value_t = false)]
     check: bool,
+    /// Headers file (80-byte headers from genesis, or `getblockheader` JSON)
+    /// to resolve the base height and reject coins younger than the base block
+    #[arg(long)]
+    headers: Option<String>,
     /// Address format: (bitcoin, testnet, signet, regtest)
     ///
     /// Error if mismatch with >=v28 dump network.
     #[arg(short, long)]
     network: Option<bitcoin::Network>,
+    /// JSON result of `dumptxoutset` saved next to the file; check the dump
+    /// against it and report each mismatch instead of printing entries
+    #[arg(long)]
+    validate: Option<String>,
+    /// Output descriptor to look for, printing the unspent outputs like
+    /// `scantxoutset` instead of entries (may be repeated)
+    #[arg(long)]
+    scan: Vec<String>,
+    /// Derivation range of ranged descriptors: `END` or `BEGIN-END`
+    #[arg(long, value_parser = parse_range)]
+    range: Option<std::ops::RangeInclusive<u32>>,
+    /// Report the N largest outputs (rank, out point, amount, height, script)
+    /// and the N richest scripts (rank, script, amount, UTXO count, min and
+    /// max height) instead of entries
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+    /// Only print entries whose script matches this opcode template, such as
+    /// `OP_DUP OP_HASH160 <20> OP_EQUALVERIFY OP_CHECKSIG`, then report
+    /// their count and value on stderr
+    #[arg(long)]
+    pattern: Option<Pattern>,
 }
 
-fn main() -> Result<(), std::io::Error> {
-    env_logger::init();
-    let args = Args::parse();
-
-    let mut stdout = std::io::stdout();
+fn parse_range(s: &str) -> Result<std::ops::RangeInclusive<u32>, std::num::ParseIntError> {
+    match s.split_once('-') {
+        Some((begin, end)) => Ok(begin.parse()?..=end.parse()?),
+        None => Ok(0..=s.parse()?),
+    }
+}
 
+/// Open the dump as configured by the arguments
+fn open(args: &Args) -> Result<Dump<BufReader<File>>, txoutset::Error> {
     let compute_addresses = if args.addresses {
         ComputeAddresses::Yes(
             args.network
@@ -48,22 +79,162 @@ fn main() -> Result<(), std::io::Error> {
         ComputeAddresses::No
     };
 
-    match Dump::new(&args.file, compute_addresses) {
-        Ok(dump) => {
+    let dump = Dump::new(&args.file, compute_addresses)?;
+    match &args.headers {
+        Some(path) => dump.with_headers(Headers::open(path)?),
+        None => Ok(dump),
+    }
+}
+
+/// Print the `n` largest outputs and the `n` richest scripts
+///
+/// The dump is read twice, the second time to aggregate per script.
+fn report_top(
+    args: &Args,
+    dump: Dump<BufReader<File>>,
+    n: usize,
+    stdout: &mut impl Write,
+) -> Result<(), txoutset::Error> {
+    let script = |script: &bitcoin::Script, address: &Option<bitcoin::Address>| match address {
+        Some(address) => address.to_string(),
+        None => hex::encode(script.as_bytes()),
+    };
+
+    writeln!(stdout, "Largest outputs:")?;
+    for (rank, tx_out) in top::top_tx_outs(dump, n)?.iter().enumerate() {
+        writeln!(
+            stdout,
+            "{},{},{},{},{}",
+            rank + 1,
+            tx_out.out_point,
+            u64::from(tx_out.amount),
+            tx_out.height,
+            script(&tx_out.script_pubkey, &tx_out.address),
+        )?;
+    }
+
+    let balances = Aggregator::new().aggregate(open(args)?)?;
+    writeln!(stdout, "Richest scripts:")?;
+    for (rank, balance) in top::top_balances(balances, n)?.iter().enumerate() {
+        writeln!(
+            stdout,
+            "{},{},{},{},{},{}",
+            rank + 1,
+            script(&balance.script_pubkey, &balance.address),
+            u64::from(balance.total_amount),
+            balance.utxo_count,
+            balance.min_height,
+            balance.max_height,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<(), std::io::Error> {
+    env_logger::init();
+    let args = Args::parse();
+
+    let mut stdout = std::io::stdout();
+
+    let dump = open(&args);
+
+    match dump {
+        Ok(mut dump) => {
             if args.check {
-                return writeln!(
+                writeln!(
                     stdout,
                     "Dump opened.\n Block Hash: {}\n UTXO Set Size: {}",
                     dump.block_hash, dump.utxo_set_size
-                );
+                )?;
+                if let Some(base_height) = dump.base_height() {
+                    writeln!(stdout, " Base Height: {}", base_height)?;
+                }
+                return Ok(());
+            }
+
+            if let Some(path) = &args.validate {
+                let report = std::fs::File::open(path)
+                    .map_err(txoutset::Error::from)
+                    .and_then(DumpInfo::from_json)
+                    .and_then(|info| txoutset::validate(dump, &info));
+                match report {
+                    Ok(report) => {
+                        writeln!(
+                            stdout,
+                            "Coins: {}\nMax Height: {}\nTxoutset Hash: {}",
+                            report.coins, report.max_height, report.txoutset_hash
+                        )?;
+                        for mismatch in &report.mismatches {
+                            writeln!(stdout, "Mismatch: {}", mismatch)?;
+                        }
+                        if !report.is_valid() {
+                            std::process::exit(1);
+                        }
+                        writeln!(stdout, "Valid.")?;
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        writeln!(std::io::stderr(), "{}: {}", e, path)?;
+                        std::process::exit(1);
+                    }
+                }
+            }
+
+            if let Some(n) = args.top {
+                if let Err(e) = report_top(&args, dump, n, &mut stdout) {
+                    writeln!(std::io::stderr(), "{}: {}", e, args.file)?;
+                    std::process::exit(1);
+                }
+                return Ok(());
+            }
+
+            if !args.scan.is_empty() {
+                let objects: Vec<_> = args
+                    .scan
+                    .iter()
+                    .map(|desc| ScanObject {
+                        desc: desc.clone(),
+                        range: args.range.clone(),
+                    })
+                    .collect();
+                match txoutset::descriptor::scan(dump, &objects) {
+                    Ok(result) => {
+                        serde_json::to_writer_pretty(&mut stdout, &result)?;
+                        writeln!(stdout)?;
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        writeln!(std::io::stderr(), "{}: {}", e, args.file)?;
+                        std::process::exit(1);
+                    }
+                }
             }
 
             let mut addr_str = String::new();
-            for item in dump {
+            let mut item = TxOut::default();
+            let mut search = Search::default();
+            loop {
+                match dump.read_into(&mut item) {
+                    Ok(true) => {}
+                    Ok(false) => break,
+                    Err(e) => {
+                        writeln!(std::io::stderr(), "{}: {}", e, args.file)?;
+                        break;
+                    }
+                }
+                if let Some(pattern) = &args.pattern {
+                    search.total.add(item.amount);
+                    if !pattern.is_match(&item.script_pubkey) {
+                        continue;
+                    }
+                    search.matched.add(item.amount);
+                }
+
                 addr_str.clear();
                 use std::fmt::Write;
 
-                match (args.addresses, item.address) {
+                match (args.addresses, &item.address) {
                     (true, Some(address)) => {
                         let _ = write!(addr_str, ",{}", address);
                     }
@@ -85,11 +256,21 @@ fn main() -> Result<(), std::io::Error> {
                 );
                 if let Err(e) = r {
                     if matches!(e.kind(), std::io::ErrorKind::BrokenPipe) {
-                        break;
+                        return Ok(());
                     }
                 }
             }
 
+            if args.pattern.is_some() {
+                writeln!(
+                    std::io::stderr(),
+                    "Matched: {} of {} entries, {} sat",
+                    search.matched.utxo_count,
+                    search.total.utxo_count,
+                    u64::from(search.matched.amount)
+                )?;
+            }
+
             Ok(())
         }
         Err(e) => {
@@ -0,0 +1,307 @@
+//! Distribution of coins by age ("HODL waves")
+//!
+//! The age of a coin is the distance from its block to the snapshot base
+//! block, in blocks or, with headers, in seconds of block time.
+
+use bitcoin::io::Read;
+
+use crate::{Amount, Dump, Error, TxOut};
+
+/// Blocks in a day at the target spacing
+const DAY_BLOCKS: u32 = 144;
+
+/// Seconds in a day
+const DAY_SECONDS: u32 = 24 * 60 * 60;
+
+/// Band boundaries in days: 1 day, 1 week, 1, 3 and 6 months, then 1, 2, 3,
+/// 5, 7 and 10 years
+const DEFAULT_DAYS: [u32; 11] = [1, 7, 30, 90, 180, 365, 730, 1095, 1825, 2555, 3650];
+
+/// How coin ages are measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgeUnit {
+    /// Blocks between the coin and the base block
+    Blocks,
+    /// Seconds between the timestamps of the coin's block and the base block
+    Seconds,
+}
+
+/// Age bands for [`age_distribution`]
+///
+/// Boundaries are ascending ages. The first band holds coins younger than the
+/// first boundary and the last band those at least as old as the last one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgeBands {
+    unit: AgeUnit,
+    bounds: Vec<u32>,
+}
+
+impl AgeBands {
+    /// Bands with boundaries in blocks
+    pub fn blocks(bounds: impl Into<Vec<u32>>) -> Self {
+        Self::new(AgeUnit::Blocks, bounds.into())
+    }
+
+    /// Bands with boundaries in seconds, which need headers
+    pub fn seconds(bounds: impl Into<Vec<u32>>) -> Self {
+        Self::new(AgeUnit::Seconds, bounds.into())
+    }
+
+    /// Boundaries at 1 day, 1 week, 1, 3 and 6 months and 1, 2, 3, 5, 7 and 10
+    /// years, counting 144 blocks per day
+    pub fn default_blocks() -> Self {
+        Self::blocks(DEFAULT_DAYS.map(|days| days * DAY_BLOCKS))
+    }
+
+    /// The boundaries of [`AgeBands::default_blocks`] in seconds
+    pub fn default_seconds() -> Self {
+        Self::seconds(DEFAULT_DAYS.map(|days| days * DAY_SECONDS))
+    }
+
+    fn new(unit: AgeUnit, mut bounds: Vec<u32>) -> Self {
+        bounds.sort_unstable();
+        bounds.dedup();
+        Self { unit, bounds }
+    }
+
+    /// How ages are measured
+    pub fn unit(&self) -> AgeUnit {
+        self.unit
+    }
+
+    /// The band boundaries
+    pub fn bounds(&self) -> &[u32] {
+        &self.bounds
+    }
+
+    /// Index of the band holding `age`
+    fn band(&self, age: u32) -> usize {
+        self.bounds.partition_point(|&bound| bound <= age)
+    }
+}
+
+impl Default for AgeBands {
+    fn default() -> Self {
+        Self::default_blocks()
+    }
+}
+
+/// Coins in one age band
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Band {
+    /// Youngest age in the band
+    pub min_age: u32,
+    /// Age at which the next band starts, `None` for the last band
+    pub max_age: Option<u32>,
+    pub utxo_count: u64,
+    pub amount: Amount,
+    /// Coinbase outputs among `utxo_count`
+    pub coinbase_count: u64,
+    /// Value of the coinbase outputs among `amount`
+    pub coinbase_amount: Amount,
+}
+
+impl Band {
+    fn add(&mut self, tx_out: &TxOut) {
+        // The dump rejects totals beyond MAX_MONEY
+        let add = |total: Amount| {
+            total
+                .checked_add_money(tx_out.amount)
+                .expect("band totals stay within MAX_MONEY")
+        };
+        self.utxo_count += 1;
+        self.amount = add(self.amount);
+        if tx_out.is_coinbase {
+            self.coinbase_count += 1;
+            self.coinbase_amount = add(self.coinbase_amount);
+        }
+    }
+}
+
+/// The result of [`age_distribution`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgeDistribution {
+    /// How ages are measured
+    pub unit: AgeUnit,
+    /// Height ages are measured from
+    pub base_height: u32,
+    /// Bands from youngest to oldest
+    pub bands: Vec<Band>,
+}
+
+/// Count the coins of `dump` and their value in each age band
+///
+/// Ages are measured from `base_height`, or from the base block of the
+/// headers supplied with [`Dump::with_headers`]. Block ages of coins above
+/// the base height and time ages of coins whose block is timestamped after
+/// the base block count as zero.
+///
+/// Fails with [`Error::NoHeaders`] without a base height, or for bands in
+/// seconds without headers.
+pub fn age_distribution<R>(
+    mut dump: Dump<R>,
+    bands: &AgeBands,
+    base_height: Option<u32>,
+) -> Result<AgeDistribution, Error>
+where
+    R: Read,
+{
+    let clock = Clock::new(&dump, bands, base_height)?;
+    let mut distribution = clock.distribution();
+
+    let mut tx_out = TxOut::default();
+    while dump.read_into(&mut tx_out)? {
+        clock.add(&mut distribution, &tx_out)?;
+    }
+
+    Ok(distribution)
+}
+
+/// Measures the ages of the coins of one dump
+pub(crate) struct Clock<'a> {
+    bands: &'a AgeBands,
+    base_height: u32,
+    /// Timestamp of the base block, for bands in seconds
+    base_time: Option<u32>,
+}
+
+impl<'a> Clock<'a> {
+    /// Resolve the base of the ages as described for [`age_distribution`]
+    pub(crate) fn new<R: Read>(
+        dump: &Dump<R>,
+        bands: &'a AgeBands,
+        base_height: Option<u32>,
+    ) -> Result<Self, Error> {
+        let base_height = base_height.or(dump.base_height()).ok_or(Error::NoHeaders)?;
+        let base_time = match bands.unit {
+            AgeUnit::Blocks => None,
+            AgeUnit::Seconds => Some(
+                dump.headers
+                    .as_ref()
+                    .and_then(|headers| headers.time(base_height))
+                    .ok_or(Error::NoHeaders)?,
+            ),
+        };
+
+        Ok(Self {
+            bands,
+            base_height,
+            base_time,
+        })
+    }
+
+    /// A distribution without coins
+    pub(crate) fn distribution(&self) -> AgeDistribution {
+        let bounds = &self.bands.bounds;
+        AgeDistribution {
+            unit: self.bands.unit,
+            base_height: self.base_height,
+            bands: (0..=bounds.len())
+                .map(|i| Band {
+                    min_age: i.checked_sub(1).map_or(0, |i| bounds[i]),
+                    max_age: bounds.get(i).copied(),
+                    ..Band::default()
+                })
+                .collect(),
+        }
+    }
+
+    /// Count a coin in the band of its age
+    pub(crate) fn add(
+        &self,
+        distribution: &mut AgeDistribution,
+        tx_out: &TxOut,
+    ) -> Result<(), Error> {
+        let age = match self.base_time {
+            None => self.base_height.saturating_sub(tx_out.height),
+            Some(base_time) => {
+                let time = tx_out.block_time.ok_or(Error::NoHeaders)?;
+                base_time.saturating_sub(time)
+            }
+        };
+        distribution.bands[self.bands.band(age)].add(tx_out);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test::DUMP_28_0;
+    use crate::{ComputeAddresses, Headers, SnapshotWriter};
+
+    fn dump() -> Dump<Cursor<&'static [u8]>> {
+        Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap()
+    }
+
+    fn counts(distribution: &AgeDistribution) -> Vec<u64> {
+        distribution
+            .bands
+            .iter()
+            .map(|band| band.utxo_count)
+            .collect()
+    }
+
+    #[test]
+    fn block_ages() {
+        // Coins at heights 1 to 100 are 0 to 99 blocks old
+        let bands = AgeBands::blocks([50, 10]);
+        let distribution = age_distribution(dump(), &bands, Some(100)).unwrap();
+        assert_eq!(counts(&distribution), [10, 40, 50]);
+        assert_eq!(distribution.bands[1].min_age, 10);
+        assert_eq!(distribution.bands[1].max_age, Some(50));
+        assert_eq!(distribution.bands[2].max_age, None);
+
+        let coinbase: u64 = distribution.bands.iter().map(|b| b.coinbase_count).sum();
+        let amount: u64 = distribution.bands.iter().map(|b| b.amount.to_sat()).sum();
+        assert_eq!(coinbase, 100);
+        assert_eq!(
+            amount,
+            dump().map(|tx_out| tx_out.amount.to_sat()).sum::<u64>()
+        );
+
+        assert!(matches!(
+            age_distribution(dump(), &bands, None),
+            Err(Error::NoHeaders)
+        ));
+        assert!(matches!(
+            age_distribution(dump(), &AgeBands::default_seconds(), Some(100)),
+            Err(Error::NoHeaders)
+        ));
+    }
+
+    #[test]
+    fn time_ages() {
+        // Regtest headers up to height 100, an hour apart
+        let mut headers = vec![bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header];
+        for _ in 0..100 {
+            let prev = headers.last().unwrap();
+            headers.push(bitcoin::block::Header {
+                prev_blockhash: prev.block_hash(),
+                time: prev.time + 3600,
+                ..*prev
+            });
+        }
+        let headers = Headers::new(0, headers).unwrap();
+
+        let mut out = Cursor::new(Vec::new());
+        let block_hash = headers.header(100).unwrap().block_hash();
+        let mut writer =
+            SnapshotWriter::new(&mut out, bitcoin::Network::Regtest, block_hash).unwrap();
+        for tx_out in dump() {
+            writer.add(tx_out).unwrap();
+        }
+        writer.finish().unwrap();
+        let dump = Dump::from_reader(Cursor::new(out.into_inner()), ComputeAddresses::No)
+            .unwrap()
+            .with_headers(headers)
+            .unwrap();
+
+        // One day is 24 blocks
+        let distribution = age_distribution(dump, &AgeBands::default_seconds(), None).unwrap();
+        assert_eq!(distribution.base_height, 100);
+        assert_eq!(counts(&distribution)[..3], [24, 76, 0]);
+    }
+}
@@ -0,0 +1,519 @@
+//! Balances and coin counts per script public key
+//!
+//! Coins are grouped in a hash map until it reaches the memory budget. The
+//! map is then sorted and spilled to a run file on disk. Once the dump is
+//! read, the runs are merged, combining the partial totals of each script.
+//! When there are more runs than can be open at once, groups of them are
+//! first merged into longer runs.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::mem::size_of;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use bitcoin::io::Read;
+use bitcoin::{Address, ScriptBuf};
+
+use crate::{Amount, Dump, Error, TxOut};
+
+/// Memory used before spilling to disk, unless configured otherwise
+pub const DEFAULT_MEMORY_BUDGET: usize = 1 << 30;
+
+/// Estimated memory of a map entry besides the script bytes
+///
+/// A slot holds the script's `Vec` header and the totals, plus a control
+/// byte. The table fills at most 7/8 of its slots and doubles as it grows, so
+/// an entry takes up to about two slots. The script's own allocation adds
+/// about two words of allocator bookkeeping.
+const ENTRY_OVERHEAD: usize = 2 * (size_of::<(Vec<u8>, Partial)>() + 1) + 2 * size_of::<usize>();
+
+/// Most run files open at once while merging
+const MAX_MERGE_RUNS: usize = 64;
+
+/// Distinguishes the run directories of aggregations in one process
+static RUN_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+/// The coins of one script public key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Balance {
+    pub script_pubkey: ScriptBuf,
+    /// The address of the script, when the dump computes addresses
+    pub address: Option<Address>,
+    pub utxo_count: u64,
+    pub total_amount: Amount,
+    /// Height of the oldest coin
+    pub min_height: u32,
+    /// Height of the youngest coin
+    pub max_height: u32,
+}
+
+/// Configuration of a per-script aggregation
+#[derive(Debug, Clone)]
+pub struct Aggregator {
+    /// Approximate memory for grouping before spilling to disk
+    memory_budget: usize,
+    /// Directory for run files
+    temp_dir: PathBuf,
+    /// Most run files merged at once
+    merge_width: usize,
+}
+
+impl Default for Aggregator {
+    fn default() -> Self {
+        Self {
+            memory_budget: DEFAULT_MEMORY_BUDGET,
+            temp_dir: std::env::temp_dir(),
+            merge_width: MAX_MERGE_RUNS,
+        }
+    }
+}
+
+impl Aggregator {
+    /// Aggregate with the default memory budget in the system temporary
+    /// directory
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spill to disk once the grouped scripts take about `bytes` of memory
+    pub fn with_memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = bytes;
+        self
+    }
+
+    /// Write run files under `path` instead of the system temporary directory
+    pub fn with_temp_dir(mut self, path: impl Into<PathBuf>) -> Self {
+        self.temp_dir = path.into();
+        self
+    }
+
+    /// Group the coins of `dump` by script public key
+    ///
+    /// Reads the whole dump before returning. The balances are produced in
+    /// ascending order of script bytes, with addresses when the dump computes
+    /// them.
+    pub fn aggregate<R>(&self, mut dump: Dump<R>) -> Result<Balances, Error>
+    where
+        R: Read,
+    {
+        let mut groups: HashMap<Vec<u8>, Partial> = HashMap::new();
+        let mut memory = 0;
+        let mut runs = None;
+
+        let mut tx_out = TxOut::default();
+        while dump.read_into(&mut tx_out)? {
+            let script = tx_out.script_pubkey.as_bytes();
+            match groups.get_mut(script) {
+                Some(partial) => partial.add(&tx_out),
+                None => {
+                    memory += script.len() + ENTRY_OVERHEAD;
+                    groups.insert(script.to_vec(), Partial::new(&tx_out));
+                }
+            }
+
+            if memory >= self.memory_budget {
+                let runs = match &mut runs {
+                    Some(runs) => runs,
+                    None => runs.insert(Runs::create(&self.temp_dir)?),
+                };
+                runs.spill(&mut groups)?;
+                memory = 0;
+            }
+        }
+
+        let source = match runs {
+            None => {
+                let mut groups: Vec<_> = groups.into_iter().collect();
+                groups.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+                Source::Memory(groups.into_iter())
+            }
+            Some(mut runs) => {
+                if !groups.is_empty() {
+                    runs.spill(&mut groups)?;
+                }
+                let merge = runs.merge(self.merge_width)?;
+                Source::Merge { merge, _runs: runs }
+            }
+        };
+
+        Ok(Balances {
+            address_network: dump.address_network,
+            error: None,
+            source,
+        })
+    }
+}
+
+/// Iterator over the aggregated balances in script order
+pub struct Balances {
+    /// Network for computing addresses
+    address_network: Option<bitcoin::Network>,
+    /// First error encountered while iterating
+    error: Option<Error>,
+    /// Where the grouped scripts come from
+    source: Source,
+}
+
+impl Balances {
+    /// The error that stopped iteration, if any
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Produce the next balance, distinguishing the end from errors
+    pub fn try_next(&mut self) -> Result<Option<Balance>, Error> {
+        let next = match &mut self.source {
+            Source::Memory(groups) => groups.next(),
+            Source::Merge { merge, .. } => merge.next_group()?,
+        };
+        let Some((script, partial)) = next else {
+            return Ok(None);
+        };
+
+        let script_pubkey = ScriptBuf::from_bytes(script);
+        let address = self
+            .address_network
+            .and_then(|network| Address::from_script(&script_pubkey, network).ok());
+        Ok(Some(Balance {
+            script_pubkey,
+            address,
+            utxo_count: partial.utxo_count,
+            total_amount: Amount::new(partial.total_amount),
+            min_height: partial.min_height,
+            max_height: partial.max_height,
+        }))
+    }
+}
+
+impl Iterator for Balances {
+    type Item = Balance;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.try_next() {
+            Ok(item) => item,
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// Totals of the coins of one script seen so far
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct Partial {
+    utxo_count: u64,
+    total_amount: u64,
+    min_height: u32,
+    max_height: u32,
+}
+
+impl Partial {
+    fn new(tx_out: &TxOut) -> Self {
+        Self {
+            utxo_count: 1,
+            total_amount: tx_out.amount.to_sat(),
+            min_height: tx_out.height,
+            max_height: tx_out.height,
+        }
+    }
+
+    fn add(&mut self, tx_out: &TxOut) {
+        self.merge(Self::new(tx_out));
+    }
+
+    /// Combine with the totals of other coins of the same script
+    ///
+    /// The dump rejects totals beyond `MAX_MONEY`, so amounts cannot overflow.
+    fn merge(&mut self, other: Self) {
+        self.utxo_count += other.utxo_count;
+        self.total_amount += other.total_amount;
+        self.min_height = self.min_height.min(other.min_height);
+        self.max_height = self.max_height.max(other.max_height);
+    }
+
+    /// Serialized size of the totals in a run file
+    const SIZE: usize = 24;
+
+    fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[..8].copy_from_slice(&self.utxo_count.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.total_amount.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.min_height.to_le_bytes());
+        bytes[20..].copy_from_slice(&self.max_height.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: [u8; Self::SIZE]) -> Self {
+        let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+        let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+        Self {
+            utxo_count: u64_at(0),
+            total_amount: u64_at(8),
+            min_height: u32_at(16),
+            max_height: u32_at(20),
+        }
+    }
+}
+
+/// Grouped scripts in script order
+enum Source {
+    /// Everything fit in the memory budget
+    Memory(std::vec::IntoIter<(Vec<u8>, Partial)>),
+    /// Sorted runs on disk
+    Merge {
+        merge: Merge,
+        /// The run files, kept until the merge is dropped
+        _runs: Runs,
+    },
+}
+
+/// A directory of run files, removed when dropped
+struct Runs {
+    dir: PathBuf,
+    /// Number of run files created so far
+    count: usize,
+    /// Runs not merged into another run yet
+    pending: Vec<usize>,
+}
+
+impl Runs {
+    fn create(temp_dir: &std::path::Path) -> Result<Self, Error> {
+        let name = format!(
+            "txoutset-aggregate-{}-{}",
+            std::process::id(),
+            RUN_DIRS.fetch_add(1, Ordering::Relaxed)
+        );
+        let dir = temp_dir.join(name);
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            count: 0,
+            pending: Vec::new(),
+        })
+    }
+
+    fn path(&self, run: usize) -> PathBuf {
+        self.dir.join(format!("run-{run}"))
+    }
+
+    /// Write the groups to a new run file in script order, emptying the map
+    fn spill(&mut self, groups: &mut HashMap<Vec<u8>, Partial>) -> Result<(), Error> {
+        let mut sorted: Vec<_> = groups.drain().collect();
+        sorted.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+
+        let mut writer = self.start()?;
+        for (script, partial) in sorted {
+            write_record(&mut writer, &script, partial)?;
+        }
+        self.finish(writer)
+    }
+
+    /// Start a new run file
+    fn start(&self) -> Result<BufWriter<File>, Error> {
+        Ok(BufWriter::new(File::create(self.path(self.count))?))
+    }
+
+    /// Complete the run file being written, leaving it to be merged
+    fn finish(&mut self, mut writer: BufWriter<File>) -> Result<(), Error> {
+        writer.flush()?;
+        self.pending.push(self.count);
+        self.count += 1;
+        Ok(())
+    }
+
+    /// Open the runs for merging, with at most `width` files open at once
+    ///
+    /// While there are more runs, the oldest `width` of them are merged into
+    /// a new run and removed.
+    fn merge(&mut self, width: usize) -> Result<Merge, Error> {
+        let width = width.max(2);
+        while self.pending.len() > width {
+            let group: Vec<_> = self.pending.drain(..width).collect();
+            let mut merge = Merge::open(self, &group)?;
+            let mut writer = self.start()?;
+            while let Some((script, partial)) = merge.next_group()? {
+                write_record(&mut writer, &script, partial)?;
+            }
+            self.finish(writer)?;
+            for run in group {
+                std::fs::remove_file(self.path(run))?;
+            }
+        }
+
+        let group = std::mem::take(&mut self.pending);
+        Merge::open(self, &group)
+    }
+}
+
+/// Write a record of a run file
+///
+/// Each record is the script size as a little-endian `u32`, the script and
+/// the totals.
+fn write_record(
+    writer: &mut BufWriter<File>,
+    script: &[u8],
+    partial: Partial,
+) -> Result<(), Error> {
+    writer.write_all(&(script.len() as u32).to_le_bytes())?;
+    writer.write_all(script)?;
+    writer.write_all(&partial.to_bytes())?;
+    Ok(())
+}
+
+impl Drop for Runs {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// K-way merge of sorted runs
+struct Merge {
+    /// The next record of each run that is not exhausted
+    heap: BinaryHeap<Reverse<(Vec<u8>, usize, Partial)>>,
+    /// Readers positioned after the record of each run in the heap
+    readers: Vec<BufReader<File>>,
+}
+
+impl Merge {
+    /// Open the `group` of runs
+    fn open(runs: &Runs, group: &[usize]) -> Result<Self, Error> {
+        let mut merge = Merge {
+            heap: BinaryHeap::with_capacity(group.len()),
+            readers: Vec::with_capacity(group.len()),
+        };
+        for (i, &run) in group.iter().enumerate() {
+            merge
+                .readers
+                .push(BufReader::new(File::open(runs.path(run))?));
+            merge.refill(i)?;
+        }
+
+        Ok(merge)
+    }
+
+    /// Combine the records of the smallest script across all runs
+    fn next_group(&mut self) -> Result<Option<(Vec<u8>, Partial)>, Error> {
+        let Some(Reverse((script, run, mut partial))) = self.heap.pop() else {
+            return Ok(None);
+        };
+        self.refill(run)?;
+
+        while let Some(Reverse((next, _, _))) = self.heap.peek() {
+            if *next != script {
+                break;
+            }
+            let Reverse((_, run, other)) = self.heap.pop().expect("peeked");
+            partial.merge(other);
+            self.refill(run)?;
+        }
+
+        Ok(Some((script, partial)))
+    }
+
+    /// Push the next record of `run` onto the heap, if any
+    fn refill(&mut self, run: usize) -> Result<(), Error> {
+        let reader = &mut self.readers[run];
+
+        let mut len = [0; 4];
+        match std::io::Read::read_exact(reader, &mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        let mut script = vec![0; u32::from_le_bytes(len) as usize];
+        std::io::Read::read_exact(reader, &mut script)?;
+        let mut partial = [0; Partial::SIZE];
+        std::io::Read::read_exact(reader, &mut partial)?;
+
+        self.heap
+            .push(Reverse((script, run, Partial::from_bytes(partial))));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test::{rewrite_scripts, DUMP_28_0};
+    use crate::{ComputeAddresses, Network};
+
+    /// The test dump with its coins paying to only seven scripts
+    fn dump() -> Dump<Cursor<Vec<u8>>> {
+        let original = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        let scripts: Vec<_> = original
+            .take(7)
+            .map(|tx_out| tx_out.script_pubkey)
+            .collect();
+
+        rewrite_scripts(ComputeAddresses::Yes(Network::Detect), |i| {
+            Some(scripts[i * i % 7].clone())
+        })
+    }
+
+    #[test]
+    fn aggregate_in_memory() {
+        let coins: Vec<_> = dump().collect();
+        let balances: Vec<_> = Aggregator::new().aggregate(dump()).unwrap().collect();
+
+        // Squares modulo 7 are 0, 1, 2 and 4
+        assert_eq!(balances.len(), 4);
+        assert!(balances
+            .windows(2)
+            .all(|pair| pair[0].script_pubkey < pair[1].script_pubkey));
+        assert_eq!(balances.iter().map(|b| b.utxo_count).sum::<u64>(), 100);
+
+        for balance in &balances {
+            let coins: Vec<_> = coins
+                .iter()
+                .filter(|tx_out| tx_out.script_pubkey == balance.script_pubkey)
+                .collect();
+            assert_eq!(balance.utxo_count, coins.len() as u64);
+            assert_eq!(
+                balance.total_amount.to_sat(),
+                coins
+                    .iter()
+                    .map(|tx_out| tx_out.amount.to_sat())
+                    .sum::<u64>()
+            );
+            assert_eq!(
+                balance.min_height,
+                coins.iter().map(|c| c.height).min().unwrap()
+            );
+            assert_eq!(
+                balance.max_height,
+                coins.iter().map(|c| c.height).max().unwrap()
+            );
+            assert_eq!(balance.address, coins[0].address);
+            assert!(balance.address.is_some());
+        }
+    }
+
+    #[test]
+    fn spill_to_disk() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let in_memory: Vec<_> = Aggregator::new().aggregate(dump()).unwrap().collect();
+
+        for (budget, merge_width) in [(0, 2), (0, 3), (0, MAX_MERGE_RUNS), (100, 2), (200, 2)] {
+            let mut aggregator = Aggregator::new()
+                .with_memory_budget(budget)
+                .with_temp_dir(temp_dir.path());
+            aggregator.merge_width = merge_width;
+            let mut balances = aggregator.aggregate(dump()).unwrap();
+            assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+
+            assert_eq!(balances.by_ref().collect::<Vec<_>>(), in_memory);
+            assert!(balances.error().is_none());
+            drop(balances);
+            assert_eq!(std::fs::read_dir(temp_dir.path()).unwrap().count(), 0);
+        }
+    }
+}
@@ -1,39 +1,111 @@
+use std::fmt;
+
+use bitcoin::amount::Denomination;
 use bitcoin::consensus::{Decodable, Encodable};
 
 use crate::VarInt;
 
+/// Error for compressed values that decompress beyond `u64::MAX`
+const DECOMPRESS_OVERFLOW: bitcoin::consensus::encode::Error =
+    bitcoin::consensus::encode::Error::ParseFailed("amount overflow");
+
 /// A compressible amount of satoshis
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Amount(u64);
 
 impl Amount {
+    /// Zero satoshis
+    pub const ZERO: Amount = Amount(0);
+    /// The theoretical maximum supply, 21 million bitcoin
+    pub const MAX_MONEY: Amount = Amount(21_000_000 * 100_000_000);
+
     pub fn new(n: u64) -> Self {
         Self(n)
     }
 
-    fn compress(&self) -> CompressedAmount {
+    /// The amount in satoshis
+    pub fn to_sat(self) -> u64 {
+        self.0
+    }
+
+    /// Whether the amount is within `0..=MAX_MONEY`, as in Bitcoin Core's
+    /// `MoneyRange`
+    pub fn is_money_range(self) -> bool {
+        self <= Self::MAX_MONEY
+    }
+
+    /// Addition, returning `None` on overflow
+    pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_add(rhs.0).map(Amount)
+    }
+
+    /// Subtraction, returning `None` if `rhs` is larger
+    pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
+        self.0.checked_sub(rhs.0).map(Amount)
+    }
+
+    /// Addition that stays within the money range, as required of a running
+    /// total of coins
+    ///
+    /// Returns `None` if the sum exceeds [`Amount::MAX_MONEY`].
+    pub fn checked_add_money(self, rhs: Amount) -> Option<Amount> {
+        self.checked_add(rhs).filter(|sum| sum.is_money_range())
+    }
+
+    /// Display the amount in the given denomination, including its unit
+    pub fn display_in(self, denomination: Denomination) -> impl fmt::Display {
+        bitcoin::Amount::from(self)
+            .display_in(denomination)
+            .show_denomination()
+    }
+
+    /// Encode the compressed form into the start of `out`, returning the
+    /// number of bytes written
+    ///
+    /// Returns `None` if `out` is too short for the encoding or the amount is
+    /// too large to compress.
+    pub fn encode_to_slice(&self, out: &mut [u8]) -> Option<usize> {
+        VarInt::from(self.compress()?).encode_to_slice(out)
+    }
+
+    /// Decode the compressed form from the start of `bytes`, returning the
+    /// amount and the number of bytes consumed
+    pub fn decode_from_slice(
+        bytes: &[u8],
+    ) -> Result<(Self, usize), bitcoin::consensus::encode::Error> {
+        let (var_int, consumed) = VarInt::decode_from_slice(bytes)?;
+        let amount = CompressedAmount::from(var_int)
+            .decompress()
+            .ok_or(DECOMPRESS_OVERFLOW)?;
+        Ok((amount, consumed))
+    }
+
+    /// Compress the amount as Bitcoin Core stores it
+    ///
+    /// Returns `None` if the compressed form does not fit in a `u64`.
+    pub fn compress(&self) -> Option<CompressedAmount> {
         let mut n = self.0;
 
         if n == 0 {
-            return CompressedAmount(0);
+            return Some(CompressedAmount(0));
         }
 
         let mut e = 0;
-        while ((n % 10) == 0) && e < 9 {
+        while n.is_multiple_of(10) && e < 9 {
             n /= 10;
             e += 1;
         }
 
         let x = if e < 9 {
+            // Nonzero, since the loop stopped before removing this digit
             let d = n % 10;
-            assert!((1..=9).contains(&d));
             n /= 10;
-            1 + (n * 9 + d - 1) * 10 + e
+            (n * 9 + d - 1).checked_mul(10)?.checked_add(1 + e)?
         } else {
-            1 + (n - 1) * 10 + 9
+            (n - 1).checked_mul(10)?.checked_add(1 + 9)?
         };
 
-        CompressedAmount(x)
+        Some(CompressedAmount(x))
     }
 }
 
@@ -42,7 +114,12 @@ impl Encodable for Amount {
         &self,
         writer: &mut W,
     ) -> Result<usize, bitcoin::io::Error> {
-        let compressed = self.compress();
+        let compressed = self.compress().ok_or_else(|| {
+            bitcoin::io::Error::new(
+                bitcoin::io::ErrorKind::InvalidInput,
+                "amount too large to compress",
+            )
+        })?;
         let var_int = VarInt::from(compressed);
 
         var_int.consensus_encode(writer)
@@ -56,7 +133,7 @@ impl Decodable for Amount {
         let var_int = VarInt::consensus_decode(reader)?;
         let compressed = CompressedAmount::from(var_int);
 
-        Ok(compressed.decompress())
+        compressed.decompress().ok_or(DECOMPRESS_OVERFLOW)
     }
 }
 
@@ -84,15 +161,33 @@ impl From<Amount> for bitcoin::Amount {
     }
 }
 
+impl fmt::Display for Amount {
+    /// Display in bitcoin, e.g. `0.001 BTC`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&bitcoin::Amount::from(*self), f)
+    }
+}
+
+/// An amount in Bitcoin Core's compressed representation
+///
+/// Trailing decimal zeros are folded into the exponent so round amounts
+/// serialize as small [`VarInt`]s.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-struct CompressedAmount(u64);
+pub struct CompressedAmount(u64);
 
 impl CompressedAmount {
-    fn decompress(&self) -> Amount {
+    pub fn new(n: u64) -> Self {
+        Self(n)
+    }
+
+    /// Expand to the amount of satoshis
+    ///
+    /// Returns `None` if the amount does not fit in a `u64`.
+    pub fn decompress(&self) -> Option<Amount> {
         let mut x = self.0;
 
         if x == 0 {
-            return Amount(0);
+            return Some(Amount(0));
         }
 
         x -= 1;
@@ -106,17 +201,17 @@ impl CompressedAmount {
             let d = (x % 9) + 1;
             x /= 9;
             // x = n
-            x * 10 + d
+            x.checked_mul(10)?.checked_add(d)?
         } else {
-            x + 1
+            x.checked_add(1)?
         };
 
         while e > 0 {
-            n *= 10;
+            n = n.checked_mul(10)?;
             e -= 1;
         }
 
-        Amount(n)
+        Some(Amount(n))
     }
 }
 
@@ -126,6 +221,12 @@ impl From<VarInt> for CompressedAmount {
     }
 }
 
+impl From<CompressedAmount> for u64 {
+    fn from(compressed: CompressedAmount) -> Self {
+        compressed.0
+    }
+}
+
 impl From<CompressedAmount> for VarInt {
     fn from(compressed: CompressedAmount) -> Self {
         VarInt::from(compressed.0)
@@ -137,6 +238,7 @@ mod test {
     use super::*;
 
     #[test]
+    #[allow(clippy::inconsistent_digit_grouping)]
     fn round_trips() {
         let amounts = vec![
             Amount(0),
@@ -149,7 +251,48 @@ mod test {
         ];
 
         for amount in amounts {
-            assert_eq!(amount, amount.compress().decompress());
+            let compressed = amount.compress().expect("compress");
+            assert_eq!(Some(amount), compressed.decompress());
+
+            let mut buf = [0; VarInt::MAX_SIZE];
+            let len = amount.encode_to_slice(&mut buf).expect("encode");
+            let decoded = Amount::decode_from_slice(&buf).expect("decode");
+            assert_eq!(decoded, (amount, len));
         }
     }
+
+    #[test]
+    fn overflow_is_an_error() {
+        assert_eq!(Amount(u64::MAX).compress(), None);
+        assert!(Amount(u64::MAX).consensus_encode(&mut Vec::new()).is_err());
+        assert_eq!(Amount(u64::MAX).encode_to_slice(&mut [0; 10]), None);
+
+        assert_eq!(CompressedAmount(u64::MAX).decompress(), None);
+        let mut buf = [0; VarInt::MAX_SIZE];
+        let len = VarInt::new(u64::MAX).encode_to_slice(&mut buf).unwrap();
+        assert!(Amount::decode_from_slice(&buf[..len]).is_err());
+        assert!(Amount::consensus_decode(&mut &buf[..len]).is_err());
+    }
+
+    #[test]
+    fn money_range() {
+        assert!(Amount::MAX_MONEY.is_money_range());
+        assert!(!Amount(Amount::MAX_MONEY.0 + 1).is_money_range());
+
+        let half = Amount(Amount::MAX_MONEY.0 / 2);
+        assert_eq!(half.checked_add_money(half), Some(Amount::MAX_MONEY));
+        assert_eq!(half.checked_add_money(Amount::MAX_MONEY), None);
+        assert_eq!(Amount(u64::MAX).checked_add(Amount(1)), None);
+        assert_eq!(Amount(1).checked_sub(Amount(2)), None);
+    }
+
+    #[test]
+    fn display() {
+        let amount = Amount(1_2345_6789);
+        assert_eq!(amount.to_string(), "1.23456789 BTC");
+        assert_eq!(
+            amount.display_in(Denomination::Satoshi).to_string(),
+            "123456789 satoshi"
+        );
+    }
 }
@@ -0,0 +1,269 @@
+//! Raw blocks from Bitcoin Core's `blk*.dat` files and standalone block files
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use bitcoin::hex::FromHex;
+use bitcoin::io::Read;
+use bitcoin::p2p::Magic;
+use bitcoin::Block;
+
+use crate::Error;
+
+/// Name of the file holding the block file obfuscation key (Core 28.0+)
+const XOR_KEY_FILE: &str = "xor.dat";
+
+/// Largest serialized block size allowed by the weight limit
+const MAX_BLOCK_SIZE: u32 = 4_000_000;
+
+/// Iterator over the blocks stored in a `blk*.dat` file
+///
+/// Each record is the network magic, the block size as a little-endian `u32`
+/// and the serialized block. Files are pre-allocated, so a zero magic marks
+/// the end of the data. Blocks appear in the order they were downloaded, not
+/// chain order.
+pub struct BlockFile<R> {
+    /// First error encountered while iterating
+    error: Option<Error>,
+    /// The framed records of the file
+    records: Records<R>,
+}
+
+impl<R> BlockFile<R>
+where
+    R: Read,
+{
+    /// Read blocks of `network` from the start of a block file
+    pub fn new(reader: R, network: bitcoin::Network) -> Self {
+        Self {
+            error: None,
+            records: Records::new(reader, network),
+        }
+    }
+
+    /// Undo the obfuscation Bitcoin Core 28.0 and later apply to block files
+    pub fn with_xor_key(mut self, xor_key: [u8; 8]) -> Self {
+        self.records.xor_key = xor_key;
+        self
+    }
+
+    /// The error that stopped iteration, if any
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Read the next block, distinguishing the end of the file from errors
+    pub fn try_next(&mut self) -> Result<Option<Block>, Error> {
+        match self.records.next_record(MAX_BLOCK_SIZE)? {
+            Some(block) => Ok(Some(bitcoin::consensus::deserialize(&block)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl BlockFile<BufReader<File>> {
+    /// Open a block file, using the `xor.dat` key next to it when present
+    pub fn open(path: impl AsRef<Path>, network: bitcoin::Network) -> Result<Self, Error> {
+        Ok(Self {
+            error: None,
+            records: Records::open(path.as_ref(), network)?,
+        })
+    }
+}
+
+impl<R> Iterator for BlockFile<R>
+where
+    R: Read,
+{
+    type Item = Block;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.try_next() {
+            Ok(item) => item,
+            Err(e) => {
+                self.records.finished = true;
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// Framed, possibly obfuscated records of a block or undo file
+pub(crate) struct Records<R> {
+    /// Set at the end of the data
+    pub(crate) finished: bool,
+    /// Network the file belongs to
+    network: bitcoin::Network,
+    /// Byte offset of the next read, for deobfuscation
+    position: u64,
+    /// The data source
+    reader: R,
+    /// Key XOR-ed over the file contents, all zero when unobfuscated
+    pub(crate) xor_key: [u8; 8],
+}
+
+impl<R> Records<R>
+where
+    R: Read,
+{
+    pub(crate) fn new(reader: R, network: bitcoin::Network) -> Self {
+        Self {
+            finished: false,
+            network,
+            position: 0,
+            reader,
+            xor_key: [0; 8],
+        }
+    }
+
+    /// Read the payload of the next record, at most `max_size` bytes long
+    pub(crate) fn next_record(&mut self, max_size: u32) -> Result<Option<Vec<u8>>, Error> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let mut magic = [0; 4];
+        match self.read_exact(&mut magic) {
+            Ok(()) => {}
+            Err(e) if e.kind() == bitcoin::io::ErrorKind::UnexpectedEof => {
+                self.finished = true;
+                return Ok(None);
+            }
+            Err(e) => return Err(e.into()),
+        }
+        if magic == [0; 4] {
+            self.finished = true;
+            return Ok(None);
+        }
+
+        let detected = bitcoin::Network::try_from(Magic::from_bytes(magic))?;
+        if detected != self.network {
+            return Err(Error::NetworkMismatch {
+                detected,
+                specified: self.network,
+            });
+        }
+
+        let mut size = [0; 4];
+        self.read_exact(&mut size)?;
+        let size = u32::from_le_bytes(size);
+        if size > max_size {
+            return Err(bitcoin::consensus::encode::Error::ParseFailed("record too large").into());
+        }
+        let mut payload = vec![0; size as usize];
+        self.read_exact(&mut payload)?;
+
+        Ok(Some(payload))
+    }
+
+    /// Fill `buf` from the file, undoing the obfuscation
+    pub(crate) fn read_exact(&mut self, buf: &mut [u8]) -> bitcoin::io::Result<()> {
+        self.reader.read_exact(buf)?;
+        for byte in buf.iter_mut() {
+            *byte ^= self.xor_key[(self.position % 8) as usize];
+            self.position += 1;
+        }
+        Ok(())
+    }
+}
+
+impl Records<BufReader<File>> {
+    /// Open a file, using the `xor.dat` key next to it when present
+    pub(crate) fn open(path: &Path, network: bitcoin::Network) -> Result<Self, Error> {
+        let reader = BufReader::new(File::open(path)?);
+
+        let key_path = path.with_file_name(XOR_KEY_FILE);
+        let xor_key = if key_path.exists() {
+            let key = std::fs::read(key_path)?;
+            key.try_into()
+                .map_err(|_| bitcoin::consensus::encode::Error::ParseFailed("bad xor.dat size"))?
+        } else {
+            [0; 8]
+        };
+
+        let mut records = Self::new(reader, network);
+        records.xor_key = xor_key;
+        Ok(records)
+    }
+}
+
+/// Read a single block serialized as binary or as hex text, such as the
+/// output of `getblock <hash> 0`
+pub fn read_block<R: Read>(mut reader: R) -> Result<Block, Error> {
+    let mut bytes = Vec::new();
+    let mut chunk = [0; 8192];
+    loop {
+        match reader.read(&mut chunk)? {
+            0 => break,
+            n => bytes.extend_from_slice(&chunk[..n]),
+        }
+    }
+
+    let text = bytes.trim_ascii();
+    if !text.is_empty() && text.iter().all(u8::is_ascii_hexdigit) {
+        let text = std::str::from_utf8(text).expect("hex digits are ASCII");
+        bytes = Vec::from_hex(text)
+            .map_err(|_| bitcoin::consensus::encode::Error::ParseFailed("invalid block hex"))?;
+    }
+
+    Ok(bitcoin::consensus::deserialize(&bytes)?)
+}
+
+/// Read a single block from a binary or hex file
+pub fn read_block_file(path: impl AsRef<Path>) -> Result<Block, Error> {
+    read_block(BufReader::new(File::open(path)?))
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::consensus::serialize;
+    use bitcoin::hex::DisplayHex;
+
+    use super::*;
+
+    #[test]
+    fn read_obfuscated_block_file() {
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let block = serialize(&genesis);
+
+        let mut file = Vec::new();
+        for _ in 0..2 {
+            file.extend_from_slice(&bitcoin::Network::Regtest.magic().to_bytes());
+            file.extend_from_slice(&(block.len() as u32).to_le_bytes());
+            file.extend_from_slice(&block);
+        }
+        // Pre-allocated space
+        file.extend_from_slice(&[0; 16]);
+
+        let key = [1, 2, 3, 4, 5, 6, 7, 8];
+        for (i, byte) in file.iter_mut().enumerate() {
+            *byte ^= key[i % 8];
+        }
+
+        let mut blocks =
+            BlockFile::new(file.as_slice(), bitcoin::Network::Regtest).with_xor_key(key);
+        assert_eq!(blocks.by_ref().count(), 2);
+        assert!(blocks.error().is_none());
+
+        let mut blocks =
+            BlockFile::new(file.as_slice(), bitcoin::Network::Signet).with_xor_key(key);
+        assert_eq!(blocks.by_ref().count(), 0);
+        assert!(matches!(
+            blocks.error(),
+            Some(Error::NetworkMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn read_hex_or_binary_block() {
+        let genesis = bitcoin::constants::genesis_block(bitcoin::Network::Regtest);
+        let block = serialize(&genesis);
+        let hex = format!("{}\n", block.to_lower_hex_string());
+
+        assert_eq!(read_block(block.as_slice()).unwrap(), genesis);
+        assert_eq!(read_block(hex.as_bytes()).unwrap(), genesis);
+        assert!(read_block(&hex.as_bytes()[1..]).is_err());
+    }
+}
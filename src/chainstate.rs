@@ -0,0 +1,365 @@
+//! Reader for Bitcoin Core's chainstate database
+//!
+//! The `chainstate/` directory of a node holds the UTXO set in LevelDB. Each
+//! coin is stored under `'C' + txid + VarInt(vout)` with the same [`Coin`]
+//! serialization used by snapshots, XOR-obfuscated with a per-database key.
+//!
+//! Opening the database writes to it, so only use a copy taken while the node
+//! was stopped. [`ChainstateWriter`] goes the other way, building a database
+//! from a snapshot.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::path::Path;
+
+use bitcoin::consensus::{serialize, Decodable, Encodable};
+use bitcoin::hashes::Hash;
+use bitcoin::io::Read;
+use bitcoin::{BlockHash, OutPoint, Txid};
+use rusty_leveldb::{DBIterator, LdbIterator, Options, WriteBatch, DB};
+
+use crate::{extend_out_point_key, Coin, ComputeAddresses, Dump, Error, Network, TxOut, VarInt};
+
+/// Key prefix of coin records
+pub(crate) const DB_COIN: u8 = b'C';
+
+/// Key of the best block hash
+pub(crate) const DB_BEST_BLOCK: &[u8] = b"B";
+
+/// Key of the obfuscation key, a serialized string with a leading zero byte
+pub(crate) const OBFUSCATE_KEY_KEY: &[u8] = b"\x0e\x00obfuscate_key";
+
+/// Iterator over the coins of an offline chainstate database
+///
+/// Produces the same [`TxOut`] entries as [`crate::Dump`], in database key
+/// order.
+pub struct Chainstate {
+    /// Optionally compute addresses using this Network
+    address_network: Option<bitcoin::Network>,
+    /// The block hash of the chain tip the coins correspond to
+    pub block_hash: BlockHash,
+    /// The open database
+    db: DB,
+    /// First error encountered while iterating
+    error: Option<Error>,
+    /// Set once the coin records are exhausted
+    finished: bool,
+    /// Cursor over the coin records, created on the first read
+    iter: Option<DBIterator>,
+    /// Key XOR-ed over every value
+    obfuscate_key: Vec<u8>,
+    /// Buffer for the deobfuscated value
+    value: Vec<u8>,
+}
+
+impl Chainstate {
+    /// Open a copied chainstate directory
+    ///
+    /// The network cannot be detected from the database, so computing
+    /// addresses requires [`Network::Specify`].
+    pub fn open<P: AsRef<Path>>(
+        path: P,
+        compute_addresses: ComputeAddresses,
+    ) -> Result<Self, Error> {
+        let address_network = match compute_addresses {
+            ComputeAddresses::No => None,
+            ComputeAddresses::Yes(Network::Specify(network)) => Some(network),
+            ComputeAddresses::Yes(Network::Detect) => return Err(Error::NetworkDetect),
+        };
+
+        let options = Options {
+            create_if_missing: false,
+            ..Options::default()
+        };
+        let mut db = DB::open(path, options)?;
+
+        // Databases created before obfuscation was introduced have no key
+        let obfuscate_key = match db.get(OBFUSCATE_KEY_KEY) {
+            Some(value) => Vec::<u8>::consensus_decode(&mut value.as_ref())?,
+            None => Vec::new(),
+        };
+
+        let mut best_block = db.get(DB_BEST_BLOCK).ok_or(Error::NoBestBlock)?.to_vec();
+        obfuscate(&mut best_block, &obfuscate_key);
+        let block_hash = BlockHash::consensus_decode(&mut best_block.as_slice())?;
+
+        Ok(Self {
+            address_network,
+            block_hash,
+            db,
+            error: None,
+            finished: false,
+            iter: None,
+            obfuscate_key,
+            value: Vec::new(),
+        })
+    }
+
+    /// The error that stopped iteration, if any
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Read the next coin, distinguishing the end of the database from errors
+    pub fn try_next(&mut self) -> Result<Option<TxOut>, Error> {
+        if self.finished {
+            return Ok(None);
+        }
+
+        let iter = match &mut self.iter {
+            Some(iter) => {
+                iter.advance();
+                iter
+            }
+            None => {
+                let mut iter = self.db.new_iter()?;
+                iter.seek(&[DB_COIN]);
+                self.iter.insert(iter)
+            }
+        };
+
+        let (key, value) = match iter.current() {
+            Some((key, value)) if key.first() == Some(&DB_COIN) => (key, value),
+            _ => {
+                self.finished = true;
+                return Ok(None);
+            }
+        };
+
+        let record_error = |source| Error::ChainstateRecord {
+            key: key.to_vec(),
+            source,
+        };
+        let out_point = decode_coin_key(&key).map_err(record_error)?;
+
+        self.value.clear();
+        self.value.extend_from_slice(&value);
+        obfuscate(&mut self.value, &self.obfuscate_key);
+        let coin = Coin::consensus_decode(&mut self.value.as_slice()).map_err(record_error)?;
+
+        Ok(Some(coin.into_tx_out(out_point, self.address_network)))
+    }
+}
+
+impl Iterator for Chainstate {
+    type Item = TxOut;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.try_next() {
+            Ok(item) => item,
+            Err(e) => {
+                self.finished = true;
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// Number of coins written per LevelDB batch
+const BATCH_SIZE: u32 = 10_000;
+
+/// Writer populating a fresh chainstate database with coins
+///
+/// The best block marker is written last by [`ChainstateWriter::finish`], so
+/// an incomplete database reads as [`Error::NoBestBlock`], the same way Core
+/// treats an interrupted flush. Bitcoin Core only starts from the result when
+/// its block index knows the best block.
+pub struct ChainstateWriter {
+    /// Coins not yet written to the database
+    batch: WriteBatch,
+    /// Number of coins added so far
+    coins: u64,
+    /// The open database
+    db: DB,
+    /// Key XOR-ed over every value
+    obfuscate_key: [u8; 8],
+    /// Buffer for the serialized coin
+    value: Vec<u8>,
+}
+
+impl ChainstateWriter {
+    /// Create a chainstate database in the directory `path`, which must not
+    /// hold one already
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        // LevelDB's own check trips over the lock file it creates first
+        if path.as_ref().join("CURRENT").exists() {
+            return Err(Error::Io(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "chainstate database already exists",
+            )));
+        }
+        let mut db = DB::open(path, Options::default())?;
+
+        // Core draws a random key for every new database
+        let obfuscate_key = RandomState::new().build_hasher().finish().to_le_bytes();
+        db.put(OBFUSCATE_KEY_KEY, &serialize(&obfuscate_key.to_vec()))?;
+
+        Ok(Self {
+            batch: WriteBatch::default(),
+            coins: 0,
+            db,
+            obfuscate_key,
+            value: Vec::new(),
+        })
+    }
+
+    /// Add a coin to the database
+    pub fn add(&mut self, tx_out: TxOut) -> Result<(), Error> {
+        let key = coin_key(&tx_out.out_point);
+
+        self.value.clear();
+        Coin::from(tx_out).consensus_encode(&mut self.value)?;
+        obfuscate(&mut self.value, &self.obfuscate_key);
+        self.batch.put(&key, &self.value);
+        self.coins += 1;
+
+        if self.batch.count() >= BATCH_SIZE {
+            self.db.write(std::mem::take(&mut self.batch), false)?;
+        }
+
+        Ok(())
+    }
+
+    /// Mark the coins as the UTXO set at `block_hash` and close the database
+    ///
+    /// Returns the number of coins written.
+    pub fn finish(mut self, block_hash: BlockHash) -> Result<u64, Error> {
+        let mut best_block = serialize(&block_hash);
+        obfuscate(&mut best_block, &self.obfuscate_key);
+        self.batch.put(DB_BEST_BLOCK, &best_block);
+        self.db.write(std::mem::take(&mut self.batch), true)?;
+        self.db.close()?;
+
+        Ok(self.coins)
+    }
+}
+
+/// Write every coin of `dump` into a new chainstate database in `path`
+///
+/// Returns the number of coins written.
+pub fn write_dump<P, R>(path: P, mut dump: Dump<R>) -> Result<u64, Error>
+where
+    P: AsRef<Path>,
+    R: Read,
+{
+    let mut writer = ChainstateWriter::create(path)?;
+    while let Some(tx_out) = dump.try_next()? {
+        writer.add(tx_out)?;
+    }
+
+    writer.finish(dump.block_hash)
+}
+
+/// Database key of the coin at `out_point`
+pub(crate) fn coin_key(out_point: &OutPoint) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + 32 + VarInt::MAX_SIZE);
+    key.push(DB_COIN);
+    extend_out_point_key(&mut key, out_point);
+    key
+}
+
+/// Out point of a coin record key
+fn decode_coin_key(key: &[u8]) -> Result<OutPoint, bitcoin::consensus::encode::Error> {
+    let txid = key
+        .get(1..33)
+        .ok_or(bitcoin::consensus::encode::Error::ParseFailed(
+            "coin key too short",
+        ))?;
+    let txid = Txid::from_slice(txid).expect("32 bytes");
+
+    let (vout, consumed) = VarInt::decode_from_slice(&key[33..])?;
+    if 33 + consumed != key.len() {
+        return Err(bitcoin::consensus::encode::Error::ParseFailed(
+            "trailing bytes in coin key",
+        ));
+    }
+    let vout = u32::try_from(u64::from(vout))
+        .map_err(|_| bitcoin::consensus::encode::Error::ParseFailed("vout out of range"))?;
+
+    Ok(OutPoint::new(txid, vout))
+}
+
+/// XOR `data` with the repeated obfuscation key, which undoes itself
+pub(crate) fn obfuscate(data: &mut [u8], key: &[u8]) {
+    if key.is_empty() {
+        return;
+    }
+    for (byte, k) in data.iter_mut().zip(key.iter().cycle()) {
+        *byte ^= k;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test::DUMP_28_0;
+
+    #[test]
+    fn read_obfuscated_chainstate() {
+        let dump = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        let block_hash = dump.block_hash;
+        let tx_outs: Vec<_> = dump.collect();
+
+        let dir = tempfile::tempdir().unwrap();
+        let key = [0x5a, 0x13, 0x00, 0xff, 0x42, 0x99, 0x01, 0xc3];
+        {
+            let mut db = DB::open(dir.path(), Options::default()).unwrap();
+            db.put(OBFUSCATE_KEY_KEY, &serialize(&key.to_vec()))
+                .unwrap();
+
+            let mut best_block = serialize(&block_hash);
+            obfuscate(&mut best_block, &key);
+            db.put(DB_BEST_BLOCK, &best_block).unwrap();
+
+            for tx_out in &tx_outs {
+                let mut value = Vec::new();
+                Coin::from(tx_out.clone())
+                    .consensus_encode(&mut value)
+                    .unwrap();
+                obfuscate(&mut value, &key);
+                db.put(&coin_key(&tx_out.out_point), &value).unwrap();
+            }
+            // Records sorting after the coins
+            db.put(b"H", &[0; 33]).unwrap();
+            db.flush().unwrap();
+        }
+
+        let mut chainstate = Chainstate::open(dir.path(), ComputeAddresses::No).unwrap();
+        assert_eq!(chainstate.block_hash, block_hash);
+        let read: Vec<_> = chainstate.by_ref().collect();
+        assert!(chainstate.error().is_none());
+        assert_eq!(read, tx_outs);
+    }
+
+    #[test]
+    fn write_dump_round_trips() {
+        let dump = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        let tx_outs: Vec<_> = dump.collect();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("chainstate");
+        let dump = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        let block_hash = dump.block_hash;
+        assert_eq!(write_dump(&path, dump).unwrap(), 100);
+
+        let mut chainstate = Chainstate::open(&path, ComputeAddresses::No).unwrap();
+        assert_eq!(chainstate.block_hash, block_hash);
+        assert_eq!(chainstate.by_ref().collect::<Vec<_>>(), tx_outs);
+        assert!(chainstate.error().is_none());
+
+        // Refuse to overwrite an existing database
+        assert!(ChainstateWriter::create(&path).is_err());
+    }
+
+    #[test]
+    fn coin_key_round_trips() {
+        let out_point = OutPoint::new(Txid::from_byte_array([7; 32]), 300);
+        let key = coin_key(&out_point);
+        assert_eq!(key.len(), 1 + 32 + 2);
+        assert_eq!(decode_coin_key(&key).unwrap(), out_point);
+        assert!(decode_coin_key(&key[..20]).is_err());
+    }
+}
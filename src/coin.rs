@@ -0,0 +1,219 @@
+//! Bitcoin Core's serialization of a single coin
+
+use bitcoin::consensus::{Decodable, Encodable};
+use bitcoin::io::{Read, Write};
+use bitcoin::{Address, OutPoint};
+
+use crate::script::Anomaly;
+use crate::{Amount, Script, TxOut, VarInt};
+
+/// An unspent output with the block metadata Bitcoin Core stores alongside it
+///
+/// Serializes as Core's `Coin`: a [`VarInt`] of the height and coinbase flag,
+/// followed by the compressed amount and script. This is the value format of
+/// the chainstate database, undo data and UTXO snapshots.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Coin {
+    /// Block height where the transaction was confirmed
+    pub height: u32,
+    /// Whether the output is in the coinbase transaction of the block
+    pub is_coinbase: bool,
+    /// The output value and script public key
+    pub txout: bitcoin::TxOut,
+    /// Set when `txout.script_pubkey` is a placeholder, see [`TxOut`]
+    pub script_anomaly: Option<Anomaly>,
+}
+
+impl Coin {
+    pub fn new(height: u32, is_coinbase: bool, txout: bitcoin::TxOut) -> Self {
+        Self {
+            height,
+            is_coinbase,
+            txout,
+            script_anomaly: None,
+        }
+    }
+
+    /// Attach an out point, optionally computing the address for `network`
+    pub fn into_tx_out(self, out_point: OutPoint, network: Option<bitcoin::Network>) -> TxOut {
+        let address = network.and_then(|network| {
+            Address::from_script(self.txout.script_pubkey.as_script(), network).ok()
+        });
+
+        TxOut {
+            address,
+            amount: Amount::from(self.txout.value),
+            block_time: None,
+            height: self.height,
+            is_coinbase: self.is_coinbase,
+            out_point,
+            script_pubkey: self.txout.script_pubkey,
+            script_anomaly: self.script_anomaly,
+        }
+    }
+}
+
+impl From<TxOut> for Coin {
+    fn from(tx_out: TxOut) -> Self {
+        Self {
+            height: tx_out.height,
+            is_coinbase: tx_out.is_coinbase,
+            txout: bitcoin::TxOut {
+                value: tx_out.amount.into(),
+                script_pubkey: tx_out.script_pubkey,
+            },
+            script_anomaly: tx_out.script_anomaly,
+        }
+    }
+}
+
+impl From<Coin> for bitcoin::TxOut {
+    fn from(coin: Coin) -> Self {
+        coin.txout
+    }
+}
+
+impl Encodable for Coin {
+    fn consensus_encode<W: Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        let code = Code {
+            height: self.height,
+            is_coinbase: self.is_coinbase,
+        };
+        let script = Script::from_parts(self.txout.script_pubkey.clone(), self.script_anomaly);
+
+        let mut len = code.consensus_encode(writer)?;
+        len += Amount::from(self.txout.value).consensus_encode(writer)?;
+        len += script.consensus_encode(writer)?;
+
+        Ok(len)
+    }
+}
+
+impl Decodable for Coin {
+    fn consensus_decode<R: Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        let code = Code::consensus_decode(reader)?;
+        let amount = Amount::consensus_decode(reader)?;
+        let script = Script::consensus_decode(reader)?;
+        let script_anomaly = script.anomaly();
+
+        Ok(Self {
+            height: code.height,
+            is_coinbase: code.is_coinbase,
+            txout: bitcoin::TxOut {
+                value: amount.into(),
+                script_pubkey: script.into_inner(),
+            },
+            script_anomaly,
+        })
+    }
+}
+
+/// Block height and coinbase flag, packed as `height * 2 + is_coinbase`
+#[derive(Debug)]
+pub(crate) struct Code {
+    pub(crate) height: u32,
+    pub(crate) is_coinbase: bool,
+}
+
+impl Encodable for Code {
+    fn consensus_encode<W: Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        let code = self
+            .height
+            .checked_mul(2)
+            .and_then(|code| code.checked_add(u32::from(self.is_coinbase)))
+            .ok_or_else(|| {
+                bitcoin::io::Error::new(bitcoin::io::ErrorKind::InvalidInput, "height too large")
+            })?;
+        let var_int = VarInt::from(code);
+
+        var_int.consensus_encode(writer)
+    }
+}
+
+impl Code {
+    /// Decode from the start of `bytes`, returning the code and the number of
+    /// bytes consumed
+    pub(crate) fn decode_from_slice(
+        bytes: &[u8],
+    ) -> Result<(Self, usize), bitcoin::consensus::encode::Error> {
+        let (var_int, consumed) = VarInt::decode_from_slice(bytes)?;
+        Ok((Self::try_from(var_int)?, consumed))
+    }
+}
+
+impl TryFrom<VarInt> for Code {
+    type Error = bitcoin::consensus::encode::Error;
+
+    fn try_from(var_int: VarInt) -> Result<Self, Self::Error> {
+        let code = u32::try_from(u64::from(var_int))
+            .map_err(|_| bitcoin::consensus::encode::Error::ParseFailed("invalid cast to u32"))?;
+
+        Ok(Code {
+            height: code >> 1,
+            is_coinbase: (code & 0x01) == 1,
+        })
+    }
+}
+
+impl Decodable for Code {
+    fn consensus_decode<R: Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        let var_int = VarInt::consensus_decode(reader)?;
+        Code::try_from(var_int)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test::DUMP_27_0;
+    use crate::{ComputeAddresses, Dump};
+
+    #[test]
+    fn decode_snapshot_coin() {
+        let first = Dump::from_reader(Cursor::new(DUMP_27_0), ComputeAddresses::No)
+            .unwrap()
+            .next()
+            .expect("first entry");
+
+        // Legacy header (block hash, count) and out point precede the coin
+        let mut bytes = &DUMP_27_0[32 + 8 + 36..];
+        let coin = Coin::consensus_decode(&mut bytes).expect("decode");
+        assert_eq!(coin, Coin::from(first.clone()));
+        assert_eq!(coin.clone().into_tx_out(first.out_point, None), first);
+
+        let mut encoded = Vec::new();
+        coin.consensus_encode(&mut encoded).expect("encode");
+        assert_eq!(encoded, DUMP_27_0[32 + 8 + 36..][..encoded.len()]);
+    }
+
+    #[test]
+    fn code_height_overflow() {
+        let code = Code {
+            height: u32::MAX / 2,
+            is_coinbase: true,
+        };
+        let mut encoded = Vec::new();
+        code.consensus_encode(&mut encoded).expect("largest code");
+        let decoded = Code::consensus_decode(&mut encoded.as_slice()).expect("decode");
+        assert_eq!(decoded.height, code.height);
+        assert!(decoded.is_coinbase);
+
+        let code = Code {
+            height: u32::MAX / 2 + 1,
+            is_coinbase: false,
+        };
+        assert!(code.consensus_encode(&mut Vec::new()).is_err());
+    }
+}
@@ -1,3 +1,4 @@
+use bitcoin::consensus::encode::Error;
 use bitcoin::consensus::{Decodable, Encodable, ReadExt};
 
 /// Compact Size
@@ -12,9 +13,65 @@ use bitcoin::consensus::{Decodable, Encodable, ReadExt};
 pub struct CompactSize(u64);
 
 impl CompactSize {
+    /// Largest encoded size
+    pub const MAX_SIZE: usize = 9;
+
     pub fn new(value: u64) -> Self {
         Self(value)
     }
+
+    /// Encode into the start of `out`, returning the number of bytes written
+    ///
+    /// Returns `None` if `out` is too short for the encoding.
+    pub fn encode_to_slice(&self, out: &mut [u8]) -> Option<usize> {
+        let (bytes, len) = self.encode_array();
+        out.get_mut(..len)?.copy_from_slice(&bytes[..len]);
+
+        Some(len)
+    }
+
+    /// Decode from the start of `bytes`, returning the value and the number of
+    /// bytes consumed
+    pub fn decode_from_slice(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let eof = || {
+            Error::from(bitcoin::io::Error::from(
+                bitcoin::io::ErrorKind::UnexpectedEof,
+            ))
+        };
+
+        let size = *bytes.first().ok_or_else(eof)?;
+        let len = match size {
+            0..=252 => return Ok((Self(size as u64), 1)),
+            253 => 2,
+            254 => 4,
+            255 => 8,
+        };
+
+        let num = bytes.get(1..1 + len).ok_or_else(eof)?;
+        let mut le = [0; 8];
+        le[..len].copy_from_slice(num);
+
+        Ok((Self(u64::from_le_bytes(le)), 1 + len))
+    }
+
+    /// Encode into a fixed buffer, returning it with the encoded length
+    fn encode_array(&self) -> ([u8; Self::MAX_SIZE], usize) {
+        let (prefix, len) = if self.0 < 253 {
+            (self.0 as u8, 1)
+        } else if self.0 <= u16::MAX as u64 {
+            (253, 3)
+        } else if self.0 <= u32::MAX as u64 {
+            (254, 5)
+        } else {
+            (255, 9)
+        };
+
+        let mut bytes = [0; Self::MAX_SIZE];
+        bytes[0] = prefix;
+        bytes[1..len].copy_from_slice(&self.0.to_le_bytes()[..len - 1]);
+
+        (bytes, len)
+    }
 }
 
 impl Encodable for CompactSize {
@@ -22,21 +79,10 @@ impl Encodable for CompactSize {
         &self,
         writer: &mut W,
     ) -> Result<usize, bitcoin::io::Error> {
-        if self.0 < 253 {
-            let n = self.0 as u8;
-            writer.write(&[n])
-        } else if self.0 <= u16::MAX as u64 {
-            let n = self.0 as u16;
-            let written = writer.write(&[253])?;
-            writer.write(&n.to_le_bytes()).map(|n| n + written)
-        } else if self.0 <= u32::MAX as u64 {
-            let n = self.0 as u32;
-            let written = writer.write(&[254])?;
-            writer.write(&n.to_le_bytes()).map(|n| n + written)
-        } else {
-            let written = writer.write(&[255])?;
-            writer.write(&self.0.to_le_bytes()).map(|n| n + written)
-        }
+        let (bytes, len) = self.encode_array();
+        writer.write_all(&bytes[..len])?;
+
+        Ok(len)
     }
 }
 
@@ -120,6 +166,13 @@ mod test {
             let mut bytes = encoded.as_slice();
             let decoded = CompactSize::consensus_decode(&mut bytes).expect("decode");
             assert_eq!(compact_size, decoded, "decode {:?} -> {}", encoded, num);
+
+            let mut buf = [0; CompactSize::MAX_SIZE];
+            let len = compact_size.encode_to_slice(&mut buf).expect("encode");
+            assert_eq!(&buf[..len], encoded);
+            let decoded = CompactSize::decode_from_slice(&encoded).expect("decode");
+            assert_eq!(decoded, (compact_size, encoded.len()));
+            assert!(CompactSize::decode_from_slice(&encoded[..len - 1]).is_err());
         }
     }
 }
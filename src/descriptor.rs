@@ -0,0 +1,466 @@
+//! Offline `scantxoutset`: finding the coins of output descriptors in a dump
+//!
+//! Descriptors are parsed with `miniscript`, plus the `addr()`, `raw()`,
+//! `rawtr()` and `combo()` forms Bitcoin Core also accepts. Ranged descriptors
+//! are derived over their range and every script is matched with a
+//! [`WatchList`].
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use bitcoin::address::NetworkUnchecked;
+use bitcoin::hex::FromHex;
+use bitcoin::io::Read;
+use bitcoin::key::TweakedPublicKey;
+use bitcoin::opcodes::all::OP_PUSHNUM_1;
+use bitcoin::secp256k1::{Secp256k1, Verification, XOnlyPublicKey};
+use bitcoin::{Address, BlockHash, Script, ScriptBuf, Txid};
+use miniscript::descriptor::checksum::desc_checksum;
+use miniscript::{Descriptor, DescriptorPublicKey};
+
+use crate::exposure::{ExposedKey, Exposure};
+use crate::{Dump, Error, TxOut, WatchList};
+
+/// Range derived from ranged descriptors without one, as in Bitcoin Core
+pub const DEFAULT_RANGE: RangeInclusive<u32> = 0..=1000;
+
+/// Most keys Bitcoin Core accepts in a bare `multi()` descriptor
+const MAX_BARE_MULTI_KEYS: usize = 3;
+
+impl TxOut {
+    /// The tightest descriptor for this coin that Bitcoin Core accepts, with
+    /// its checksum
+    ///
+    /// The descriptor depends on `script_pubkey` alone, not on the network or
+    /// whether addresses were computed. Keys revealed by the script render as
+    /// `pk()`, bare `multi()` of up to three keys and `rawtr()`, since `tr()`
+    /// would tweak the output key again. Everything else is `raw()`: scripts
+    /// committing to key hashes cannot render as `pkh()` or `wpkh()` without
+    /// the key.
+    pub fn descriptor(&self) -> String {
+        let script = self.script_pubkey.as_script();
+        let keys = self.exposed_pubkeys();
+        let desc = match (self.exposure(), keys.as_slice()) {
+            (Some(Exposure::Multisig), _) => multi(script, &keys),
+            (_, [ExposedKey::Ecdsa(key)]) => Some(format!("pk({key})")),
+            (_, [ExposedKey::Taproot(key)]) => Some(format!("rawtr({key})")),
+            _ => None,
+        }
+        .unwrap_or_else(|| format!("raw({script:x})"));
+
+        checksummed(desc).expect("rendered descriptors use checksum characters")
+    }
+}
+
+/// A descriptor to scan for, with the range of indexes to derive
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanObject {
+    /// The output descriptor, optionally with its checksum
+    pub desc: String,
+    /// Derivation indexes for ranged descriptors, [`DEFAULT_RANGE`] if `None`
+    pub range: Option<RangeInclusive<u32>>,
+}
+
+impl From<&str> for ScanObject {
+    fn from(desc: &str) -> Self {
+        Self {
+            desc: desc.to_owned(),
+            range: None,
+        }
+    }
+}
+
+/// An unspent output found by [`scan`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct Unspent {
+    pub txid: Txid,
+    pub vout: u32,
+    #[cfg_attr(feature = "json", serde(rename = "scriptPubKey"))]
+    pub script_pubkey: ScriptBuf,
+    /// The derived descriptor that produced the script
+    pub desc: String,
+    #[cfg_attr(feature = "json", serde(with = "bitcoin::amount::serde::as_btc"))]
+    pub amount: bitcoin::Amount,
+    pub coinbase: bool,
+    pub height: u32,
+}
+
+/// The result of [`scan`], shaped like the result of `scantxoutset start`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct ScanResult {
+    pub success: bool,
+    /// Number of entries in the dump
+    pub txouts: u64,
+    /// Height of the snapshot base block, when headers were supplied
+    #[cfg_attr(feature = "json", serde(skip_serializing_if = "Option::is_none"))]
+    pub height: Option<u32>,
+    /// The snapshot base block
+    pub bestblock: BlockHash,
+    pub unspents: Vec<Unspent>,
+    #[cfg_attr(feature = "json", serde(with = "bitcoin::amount::serde::as_btc"))]
+    pub total_amount: bitcoin::Amount,
+}
+
+/// Find the unspent outputs of `objects` in `dump`
+///
+/// Unlike Bitcoin Core, the `desc` of each result is the derived descriptor
+/// without key origin information.
+pub fn scan<R>(dump: Dump<R>, objects: &[ScanObject]) -> Result<ScanResult, Error>
+where
+    R: Read,
+{
+    let mut descs = HashMap::new();
+    for object in objects {
+        let range = object.range.clone().unwrap_or(DEFAULT_RANGE);
+        for (script, desc) in derive(&object.desc, range)? {
+            descs.entry(script).or_insert(desc);
+        }
+    }
+    let watch_list: WatchList = descs.keys().cloned().collect();
+
+    let txouts = dump.utxo_set_size;
+    let bestblock = dump.block_hash;
+    let height = dump.base_height();
+
+    let mut unspents = Vec::new();
+    let mut total_amount = bitcoin::Amount::ZERO;
+    let mut matches = watch_list.matches(dump);
+    while let Some(tx_out) = matches.try_next()? {
+        // Watch list keys are compact and could in principle match another
+        // script
+        let Some(desc) = descs.get(&tx_out.script_pubkey) else {
+            continue;
+        };
+        let amount = bitcoin::Amount::from(tx_out.amount);
+        total_amount += amount;
+        unspents.push(Unspent {
+            txid: tx_out.out_point.txid,
+            vout: tx_out.out_point.vout,
+            desc: desc.clone(),
+            script_pubkey: tx_out.script_pubkey,
+            amount,
+            coinbase: tx_out.is_coinbase,
+            height: tx_out.height,
+        });
+    }
+
+    Ok(ScanResult {
+        success: true,
+        txouts,
+        height,
+        bestblock,
+        unspents,
+        total_amount,
+    })
+}
+
+/// Derive the script public keys of a descriptor over `range`, each with the
+/// derived descriptor that produced it
+///
+/// Descriptors without wildcards ignore the range.
+pub fn derive(
+    descriptor: &str,
+    range: RangeInclusive<u32>,
+) -> Result<Vec<(ScriptBuf, String)>, Error> {
+    let invalid = |reason: String| Error::Descriptor {
+        descriptor: descriptor.to_owned(),
+        reason,
+    };
+    let secp = Secp256k1::verification_only();
+
+    let body = match descriptor.split_once('#') {
+        Some((body, checksum)) => {
+            let expected = desc_checksum(body).map_err(|e| invalid(e.to_string()))?;
+            if checksum != expected {
+                return Err(invalid(format!("checksum {checksum}, expected {expected}")));
+            }
+            body
+        }
+        None => descriptor,
+    };
+
+    if let Some(address) = argument(body, "addr") {
+        let address = Address::<NetworkUnchecked>::from_str(address)
+            .map_err(|e| invalid(e.to_string()))?
+            .assume_checked();
+        return Ok(vec![with_desc(address.script_pubkey(), "addr", address)?]);
+    }
+    if let Some(hex) = argument(body, "raw") {
+        let script = ScriptBuf::from_bytes(Vec::from_hex(hex).map_err(|e| invalid(e.to_string()))?);
+        let desc = format!("raw({:x})", script);
+        return Ok(vec![(script, checksummed(desc)?)]);
+    }
+    if let Some(key) = argument(body, "rawtr") {
+        let key = XOnlyPublicKey::from_str(key).map_err(|e| invalid(e.to_string()))?;
+        let script = ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(key));
+        return Ok(vec![with_desc(script, "rawtr", key)?]);
+    }
+    if let Some(key) = argument(body, "combo") {
+        let key = DescriptorPublicKey::from_str(key).map_err(|e| invalid(e.to_string()))?;
+        let mut scripts = Vec::new();
+        for key in key.into_single_keys() {
+            for index in indexes(key.has_wildcard(), &range) {
+                let key = key
+                    .clone()
+                    .at_derivation_index(index)
+                    .and_then(|key| key.derive_public_key(&secp))
+                    .map_err(|e| invalid(e.to_string()))?;
+                scripts.extend(combo(key)?);
+            }
+        }
+        return Ok(scripts);
+    }
+
+    let descriptor = Descriptor::<DescriptorPublicKey>::from_str(descriptor)
+        .map_err(|e| invalid(e.to_string()))?;
+    let mut scripts = Vec::new();
+    for descriptor in descriptor
+        .into_single_descriptors()
+        .map_err(|e| invalid(e.to_string()))?
+    {
+        for index in indexes(descriptor.has_wildcard(), &range) {
+            let derived = derived(&descriptor, index, &secp).map_err(|e| invalid(e.to_string()))?;
+            scripts.push((derived.script_pubkey(), derived.to_string()));
+        }
+    }
+
+    Ok(scripts)
+}
+
+/// The argument of `name(...)`, if `body` has that form
+fn argument<'a>(body: &'a str, name: &str) -> Option<&'a str> {
+    body.strip_prefix(name)?
+        .strip_prefix('(')?
+        .strip_suffix(')')
+}
+
+/// Indexes to derive, just one for descriptors without wildcards
+fn indexes(has_wildcard: bool, range: &RangeInclusive<u32>) -> RangeInclusive<u32> {
+    if has_wildcard {
+        range.clone()
+    } else {
+        0..=0
+    }
+}
+
+/// A descriptor with all keys derived at `index`
+fn derived<C: Verification>(
+    descriptor: &Descriptor<DescriptorPublicKey>,
+    index: u32,
+    secp: &Secp256k1<C>,
+) -> Result<Descriptor<bitcoin::PublicKey>, miniscript::descriptor::ConversionError> {
+    descriptor
+        .at_derivation_index(index)?
+        .derived_descriptor(secp)
+}
+
+/// The scripts of `combo(key)`: P2PK and P2PKH, plus P2WPKH and P2SH-P2WPKH
+/// for compressed keys
+fn combo(key: bitcoin::PublicKey) -> Result<Vec<(ScriptBuf, String)>, Error> {
+    let mut scripts = vec![
+        with_desc(ScriptBuf::new_p2pk(&key), "pk", key)?,
+        with_desc(ScriptBuf::new_p2pkh(&key.pubkey_hash()), "pkh", key)?,
+    ];
+    if let Ok(wpubkey_hash) = key.wpubkey_hash() {
+        let p2wpkh = ScriptBuf::new_p2wpkh(&wpubkey_hash);
+        let p2sh = ScriptBuf::new_p2sh(&p2wpkh.script_hash());
+        scripts.push(with_desc(p2wpkh, "wpkh", key)?);
+        scripts.push((p2sh, checksummed(format!("sh(wpkh({key}))"))?));
+    }
+
+    Ok(scripts)
+}
+
+/// The `multi()` descriptor of a bare multisig script whose pushes are all
+/// valid keys
+fn multi(script: &Script, keys: &[ExposedKey]) -> Option<String> {
+    // OP_1 to OP_16
+    let pushnum = |byte: u8| {
+        let n = byte.checked_sub(OP_PUSHNUM_1.to_u8())?;
+        (n < 16).then_some(usize::from(n) + 1)
+    };
+    let bytes = script.as_bytes();
+    let required = pushnum(bytes[0])?;
+    let count = pushnum(bytes[bytes.len() - 2])?;
+    if keys.len() != count || count > MAX_BARE_MULTI_KEYS {
+        return None;
+    }
+
+    let keys: Vec<_> = keys
+        .iter()
+        .map(|key| match key {
+            ExposedKey::Ecdsa(key) => key.to_string(),
+            ExposedKey::Taproot(key) => key.to_string(),
+        })
+        .collect();
+    Some(format!("multi({required},{})", keys.join(",")))
+}
+
+/// Pair a script with the descriptor `name(argument)`
+fn with_desc(
+    script: ScriptBuf,
+    name: &str,
+    argument: impl std::fmt::Display,
+) -> Result<(ScriptBuf, String), Error> {
+    Ok((script, checksummed(format!("{name}({argument})"))?))
+}
+
+/// Append the checksum to a descriptor
+fn checksummed(desc: String) -> Result<String, Error> {
+    match desc_checksum(&desc) {
+        Ok(checksum) => Ok(format!("{desc}#{checksum}")),
+        Err(e) => Err(Error::Descriptor {
+            descriptor: desc,
+            reason: e.to_string(),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test::DUMP_28_0;
+    use crate::ComputeAddresses;
+
+    fn dump() -> Dump<Cursor<&'static [u8]>> {
+        Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap()
+    }
+
+    #[test]
+    fn scan_raw_and_addr() {
+        let all: Vec<_> = dump().collect();
+        let address =
+            Address::from_script(&all[5].script_pubkey, bitcoin::Network::Signet).unwrap();
+        let objects = [
+            ScanObject::from(format!("raw({:x})", all[7].script_pubkey).as_str()),
+            ScanObject::from(checksummed(format!("addr({address})")).unwrap().as_str()),
+        ];
+
+        let result = scan(dump(), &objects).unwrap();
+        assert_eq!(result.txouts, 100);
+        assert_eq!(result.bestblock, dump().block_hash);
+        assert_eq!(result.unspents.len(), 2);
+        assert_eq!(result.unspents[0].txid, all[5].out_point.txid);
+        assert!(result.unspents[0]
+            .desc
+            .starts_with(&format!("addr({address})#")));
+        assert_eq!(result.unspents[1].script_pubkey, all[7].script_pubkey);
+        assert_eq!(
+            result.total_amount,
+            bitcoin::Amount::from(all[5].amount) + bitcoin::Amount::from(all[7].amount)
+        );
+
+        let bad = ScanObject::from(format!("addr({address})#00000000").as_str());
+        assert!(matches!(
+            scan(dump(), &[bad]),
+            Err(Error::Descriptor { .. })
+        ));
+    }
+
+    #[test]
+    fn derive_ranged_and_combo() {
+        // BIP 86 test vector
+        let xpub = "xpub6BgBgsespWvERF3LHQu6CnqdvfEvtMcQjYrcRzx53QJjSxarj2afYWcLteoGVky7D3UKDP9QyrLprQ3VCECoY49yfdDEHGCtMMj92pReUsQ";
+        let scripts = derive(&format!("tr({xpub}/0/*)"), 0..=2).unwrap();
+        assert_eq!(scripts.len(), 3);
+        assert_eq!(
+            scripts[0].1,
+            "tr(03cc8a4bc64d897bddc5fbc2f670f7a8ba0b386779106cf1223c6fc5d7cd6fc115)#6qm9h8ym"
+        );
+
+        let scripts = derive(&format!("combo({xpub}/0/*)"), 5..=6).unwrap();
+        assert_eq!(scripts.len(), 8);
+        assert!(scripts[3].0.is_p2sh());
+        assert!(scripts[3].1.starts_with("sh(wpkh("));
+
+        let scripts = derive(&format!("wpkh({xpub}/0)"), 0..=9).unwrap();
+        assert_eq!(scripts.len(), 1);
+        assert!(scripts[0].0.is_p2wpkh());
+    }
+
+    #[test]
+    fn render_tx_outs() {
+        let key: bitcoin::PublicKey =
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+                .parse()
+                .unwrap();
+        let uncompressed = bitcoin::PublicKey::new_uncompressed(key.inner);
+        let multisig = |last: &[u8]| {
+            bitcoin::script::Builder::new()
+                .push_opcode(bitcoin::opcodes::all::OP_PUSHNUM_2)
+                .push_key(&uncompressed)
+                .push_slice(<&bitcoin::script::PushBytes>::try_from(last).unwrap())
+                .push_opcode(bitcoin::opcodes::all::OP_PUSHNUM_2)
+                .push_opcode(bitcoin::opcodes::all::OP_CHECKMULTISIG)
+                .into_script()
+        };
+        let p2tr = ScriptBuf::new_p2tr_tweaked(TweakedPublicKey::dangerous_assume_tweaked(
+            key.inner.into(),
+        ));
+
+        let cases = [
+            (ScriptBuf::new_p2pk(&key), format!("pk({key})")),
+            (
+                ScriptBuf::new_p2pk(&uncompressed),
+                format!("pk({uncompressed})"),
+            ),
+            (
+                multisig(&key.to_bytes()),
+                format!("multi(2,{uncompressed},{key})"),
+            ),
+            (multisig(&[5; 33]), format!("raw({:x})", multisig(&[5; 33]))),
+            (p2tr, format!("rawtr({})", key.inner.x_only_public_key().0)),
+        ];
+        for (script_pubkey, expected) in cases {
+            let tx_out = TxOut {
+                script_pubkey: script_pubkey.clone(),
+                ..TxOut::default()
+            };
+            let desc = tx_out.descriptor();
+            assert_eq!(desc, checksummed(expected).unwrap());
+            assert_eq!(derive(&desc, 0..=0).unwrap(), [(script_pubkey, desc)]);
+        }
+
+        // Hash-committing scripts render the same with or without addresses
+        let tx_out = dump().next().unwrap();
+        let raw = tx_out.descriptor();
+        assert!(raw.starts_with("raw(0014"));
+        assert_eq!(derive(&raw, 0..=0).unwrap()[0].0, tx_out.script_pubkey);
+        let addresses = ComputeAddresses::Yes(crate::Network::Detect);
+        let addressed = Dump::from_reader(Cursor::new(DUMP_28_0), addresses).unwrap();
+        for (tx_out, addressed) in dump().zip(addressed) {
+            assert!(addressed.address.is_some());
+            assert_eq!(addressed.descriptor(), tx_out.descriptor());
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn scantxoutset_json() {
+        let all: Vec<_> = dump().collect();
+        let object = ScanObject::from(format!("raw({:x})", all[0].script_pubkey).as_str());
+        let result = scan(dump(), &[object]).unwrap();
+
+        let json = serde_json::to_value(&result).unwrap();
+        assert_eq!(json["success"], true);
+        assert_eq!(json["txouts"], 100);
+        assert!(json.get("height").is_none());
+        let unspent = &json["unspents"][0];
+        assert_eq!(unspent["vout"], all[0].out_point.vout);
+        assert_eq!(
+            unspent["scriptPubKey"],
+            format!("{:x}", all[0].script_pubkey)
+        );
+        assert_eq!(unspent["coinbase"], true);
+        assert_eq!(unspent["height"], all[0].height);
+        assert_eq!(
+            json["total_amount"].as_f64(),
+            Some(bitcoin::Amount::from(all[0].amount).to_btc())
+        );
+    }
+}
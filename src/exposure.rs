@@ -0,0 +1,290 @@
+//! Coins whose public keys are revealed by their script alone
+//!
+//! P2PK, bare multisig and P2TR outputs commit to public keys directly rather
+//! than to their hashes. An attacker able to compute discrete logarithms, such
+//! as a large quantum computer, could spend them without waiting for the keys
+//! to appear in a spending transaction.
+
+use std::collections::BTreeMap;
+
+use bitcoin::io::Read;
+use bitcoin::script::Instruction;
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::Script;
+
+use crate::age::{AgeBands, AgeDistribution, Clock};
+use crate::spend::Tally;
+use crate::{Dump, Error, TxOut};
+
+/// The kind of script revealing public keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Exposure {
+    /// Pay to a compressed public key
+    P2pkCompressed,
+    /// Pay to an uncompressed public key
+    P2pkUncompressed,
+    /// Bare `OP_CHECKMULTISIG`
+    Multisig,
+    /// Taproot output key
+    P2tr,
+}
+
+/// A public key revealed by a script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExposedKey {
+    /// An ECDSA key of a P2PK or multisig script
+    Ecdsa(bitcoin::PublicKey),
+    /// A taproot output key
+    Taproot(XOnlyPublicKey),
+}
+
+impl TxOut {
+    /// The kind of script revealing public keys, if any
+    ///
+    /// A script is exposed only if it reveals at least one valid key, so P2PK
+    /// and P2TR keys off the curve and multisig scripts without a valid key
+    /// are not.
+    pub fn exposure(&self) -> Option<Exposure> {
+        let script = self.script_pubkey.as_script();
+        if let Some(key) = script.p2pk_public_key() {
+            Some(if key.compressed {
+                Exposure::P2pkCompressed
+            } else {
+                Exposure::P2pkUncompressed
+            })
+        } else if script.is_multisig() && multisig_keys(script).next().is_some() {
+            Some(Exposure::Multisig)
+        } else if taproot_key(script).is_some() {
+            Some(Exposure::P2tr)
+        } else {
+            None
+        }
+    }
+
+    /// The valid public keys revealed by the script alone
+    ///
+    /// Empty exactly when [`TxOut::exposure`] is `None`. Multisig pushes that
+    /// are not valid keys are left out.
+    pub fn exposed_pubkeys(&self) -> Vec<ExposedKey> {
+        let script = self.script_pubkey.as_script();
+        match self.exposure() {
+            Some(Exposure::P2pkCompressed | Exposure::P2pkUncompressed) => script
+                .p2pk_public_key()
+                .map(ExposedKey::Ecdsa)
+                .into_iter()
+                .collect(),
+            Some(Exposure::Multisig) => multisig_keys(script).map(ExposedKey::Ecdsa).collect(),
+            Some(Exposure::P2tr) => taproot_key(script)
+                .map(ExposedKey::Taproot)
+                .into_iter()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// The pushes of a multisig script that are valid keys
+fn multisig_keys(script: &Script) -> impl Iterator<Item = bitcoin::PublicKey> + '_ {
+    script
+        .instructions()
+        .filter_map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) => {
+                bitcoin::PublicKey::from_slice(bytes.as_bytes()).ok()
+            }
+            _ => None,
+        })
+}
+
+/// The output key of a P2TR script, if it is on the curve
+fn taproot_key(script: &Script) -> Option<XOnlyPublicKey> {
+    if !script.is_p2tr() {
+        return None;
+    }
+    XOnlyPublicKey::from_slice(&script.as_bytes()[2..]).ok()
+}
+
+/// Count and value of the coins with exposed keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExposureReport {
+    /// Every coin of the dump
+    pub total: Tally,
+    /// Coins with exposed keys
+    pub exposed: Tally,
+    /// Coins with exposed keys by kind of script
+    pub by_type: BTreeMap<Exposure, Tally>,
+    /// Coins with exposed keys by age
+    pub by_age: AgeDistribution,
+}
+
+/// Tally the coins of `dump` with exposed public keys
+///
+/// Ages are measured as by [`crate::age::age_distribution`], with the same
+/// requirements for a base height and headers.
+pub fn exposure_report<R>(
+    mut dump: Dump<R>,
+    bands: &AgeBands,
+    base_height: Option<u32>,
+) -> Result<ExposureReport, Error>
+where
+    R: Read,
+{
+    let clock = Clock::new(&dump, bands, base_height)?;
+    let mut report = ExposureReport {
+        total: Tally::default(),
+        exposed: Tally::default(),
+        by_type: BTreeMap::new(),
+        by_age: clock.distribution(),
+    };
+
+    let mut tx_out = TxOut::default();
+    while dump.read_into(&mut tx_out)? {
+        report.total.add(tx_out.amount);
+        let Some(exposure) = tx_out.exposure() else {
+            continue;
+        };
+        report.exposed.add(tx_out.amount);
+        report
+            .by_type
+            .entry(exposure)
+            .or_default()
+            .add(tx_out.amount);
+        clock.add(&mut report.by_age, &tx_out)?;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::TweakedPublicKey;
+    use bitcoin::script::Builder;
+    use bitcoin::{opcodes, ScriptBuf, WPubkeyHash};
+
+    use super::*;
+    use crate::test::rewrite_scripts;
+    use crate::{Amount, ComputeAddresses};
+
+    fn key() -> bitcoin::PublicKey {
+        // The generator point
+        "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+            .parse()
+            .unwrap()
+    }
+
+    fn tx_out(script_pubkey: ScriptBuf) -> TxOut {
+        TxOut {
+            script_pubkey,
+            ..TxOut::default()
+        }
+    }
+
+    fn multisig() -> ScriptBuf {
+        Builder::new()
+            .push_opcode(opcodes::all::OP_PUSHNUM_1)
+            .push_key(&key())
+            .push_slice([5; 33])
+            .push_opcode(opcodes::all::OP_PUSHNUM_2)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    fn p2tr() -> ScriptBuf {
+        let output_key = TweakedPublicKey::dangerous_assume_tweaked(key().inner.into());
+        ScriptBuf::new_p2tr_tweaked(output_key)
+    }
+
+    /// An x-coordinate without a point on the curve
+    fn off_curve() -> [u8; 32] {
+        let mut x = [0; 32];
+        x[31] = 5;
+        x
+    }
+
+    /// Scripts shaped like P2PK, multisig and P2TR without a valid key
+    fn invalid() -> [ScriptBuf; 3] {
+        let mut compressed = [2; 33];
+        compressed[1..].copy_from_slice(&off_curve());
+        [
+            Builder::new()
+                .push_slice(compressed)
+                .push_opcode(opcodes::all::OP_CHECKSIG)
+                .into_script(),
+            Builder::new()
+                .push_opcode(opcodes::all::OP_PUSHNUM_1)
+                .push_slice(compressed)
+                .push_slice([5; 33])
+                .push_opcode(opcodes::all::OP_PUSHNUM_2)
+                .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+                .into_script(),
+            Builder::new()
+                .push_opcode(opcodes::all::OP_PUSHNUM_1)
+                .push_slice(off_curve())
+                .into_script(),
+        ]
+    }
+
+    #[test]
+    fn exposed_keys() {
+        let uncompressed = bitcoin::PublicKey::new_uncompressed(key().inner);
+
+        let p2pk = tx_out(ScriptBuf::new_p2pk(&key()));
+        assert_eq!(p2pk.exposure(), Some(Exposure::P2pkCompressed));
+        assert_eq!(p2pk.exposed_pubkeys(), [ExposedKey::Ecdsa(key())]);
+
+        let p2pk = tx_out(ScriptBuf::new_p2pk(&uncompressed));
+        assert_eq!(p2pk.exposure(), Some(Exposure::P2pkUncompressed));
+        assert_eq!(p2pk.exposed_pubkeys(), [ExposedKey::Ecdsa(uncompressed)]);
+
+        // The second push is not a valid key
+        let multisig = tx_out(multisig());
+        assert_eq!(multisig.exposure(), Some(Exposure::Multisig));
+        assert_eq!(multisig.exposed_pubkeys(), [ExposedKey::Ecdsa(key())]);
+
+        let p2tr = tx_out(p2tr());
+        assert_eq!(p2tr.exposure(), Some(Exposure::P2tr));
+        assert_eq!(
+            p2tr.exposed_pubkeys(),
+            [ExposedKey::Taproot(key().inner.into())]
+        );
+
+        let p2wpkh = tx_out(ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros()));
+        assert_eq!(p2wpkh.exposure(), None);
+        assert!(p2wpkh.exposed_pubkeys().is_empty());
+
+        for script in invalid() {
+            let tx_out = tx_out(script);
+            assert_eq!(tx_out.exposure(), None, "{}", tx_out.script_pubkey);
+            assert!(tx_out.exposed_pubkeys().is_empty());
+        }
+        assert!(invalid()[1].is_multisig());
+        assert!(invalid()[2].is_p2tr());
+    }
+
+    #[test]
+    fn report_by_type_and_age() {
+        // The test dump with its first coins paying to exposed keys, then
+        // to scripts of the same shapes without a valid key
+        let exposed = [ScriptBuf::new_p2pk(&key()), multisig(), p2tr()];
+        let scripts: Vec<_> = exposed.into_iter().chain(invalid()).collect();
+        let dump = || rewrite_scripts(ComputeAddresses::No, |i| scripts.get(i).cloned());
+        let exposed: Vec<_> = dump().take(3).collect();
+
+        let bands = AgeBands::blocks([50]);
+        let report = exposure_report(dump(), &bands, Some(100)).unwrap();
+        assert_eq!(report.total.utxo_count, 100);
+        assert_eq!(report.exposed.utxo_count, 3);
+        assert_eq!(
+            report.exposed.amount.to_sat(),
+            exposed.iter().map(|t| t.amount.to_sat()).sum::<u64>()
+        );
+        assert_eq!(report.by_type.len(), 3);
+        assert_eq!(report.by_type[&Exposure::Multisig].utxo_count, 1);
+        assert_eq!(report.by_type[&Exposure::P2tr].amount, exposed[2].amount);
+
+        let young = exposed.iter().filter(|t| 100 - t.height < 50).count() as u64;
+        assert_eq!(report.by_age.bands[0].utxo_count, young);
+        assert_eq!(report.by_age.bands[1].utxo_count, 3 - young);
+        assert_ne!(report.exposed.amount, Amount::ZERO);
+    }
+}
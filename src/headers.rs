@@ -0,0 +1,275 @@
+//! Block headers for resolving snapshot heights and block times
+//!
+//! A snapshot records its base block hash but not the height or time. A
+//! [`Headers`] chain supplies both, from a file of consecutive 80-byte headers
+//! or, with the `json` feature, from Bitcoin Core's `getblockheader` output.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use bitcoin::block::Header;
+use bitcoin::consensus::Decodable;
+use bitcoin::io::Read;
+use bitcoin::BlockHash;
+
+use crate::Error;
+
+/// A chain of consecutive block headers
+#[derive(Debug, Clone)]
+pub struct Headers {
+    /// Height of each block by hash
+    heights: HashMap<BlockHash, u32>,
+    /// Headers in height order
+    headers: Vec<Header>,
+    /// Height of the first header
+    start: u32,
+}
+
+impl Headers {
+    /// Build a chain from headers in height order, the first at `start`
+    ///
+    /// Fails with [`Error::HeaderChain`] if a header does not link to the one
+    /// before it.
+    pub fn new(start: u32, headers: Vec<Header>) -> Result<Self, Error> {
+        let mut heights = HashMap::with_capacity(headers.len());
+        let mut prev_blockhash = None;
+        for (height, header) in (start..).zip(&headers) {
+            if prev_blockhash.is_some_and(|prev_blockhash| prev_blockhash != header.prev_blockhash)
+            {
+                return Err(Error::HeaderChain { height });
+            }
+            let block_hash = header.block_hash();
+            heights.insert(block_hash, height);
+            prev_blockhash = Some(block_hash);
+        }
+
+        Ok(Self {
+            heights,
+            headers,
+            start,
+        })
+    }
+
+    /// Read consecutive 80-byte headers, starting with the genesis block
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<Self, Error> {
+        let mut headers = Vec::new();
+        let mut bytes = [0; 80];
+        loop {
+            // Stop cleanly only at a header boundary
+            let mut filled = 0;
+            while filled < bytes.len() {
+                match reader.read(&mut bytes[filled..]) {
+                    Ok(0) => break,
+                    Ok(n) => filled += n,
+                    Err(e) if e.kind() == bitcoin::io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            match filled {
+                0 => break,
+                80 => headers.push(Header::consensus_decode(&mut bytes.as_slice())?),
+                _ => {
+                    return Err(
+                        bitcoin::io::Error::from(bitcoin::io::ErrorKind::UnexpectedEof).into(),
+                    )
+                }
+            }
+        }
+
+        Self::new(0, headers)
+    }
+
+    /// Read a headers file, either binary or `getblockheader` JSON
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let is_json = std::io::BufRead::fill_buf(&mut reader)?
+            .iter()
+            .find(|byte| !byte.is_ascii_whitespace())
+            .is_some_and(|byte| matches!(byte, b'{' | b'['));
+
+        if is_json {
+            #[cfg(feature = "json")]
+            return Self::from_json(reader);
+            #[cfg(not(feature = "json"))]
+            return Err(bitcoin::consensus::encode::Error::ParseFailed(
+                "JSON headers need the `json` feature",
+            )
+            .into());
+        }
+
+        Self::from_reader(reader)
+    }
+
+    /// Height of the first header
+    pub fn start_height(&self) -> u32 {
+        self.start
+    }
+
+    /// Height of the last header
+    pub fn tip_height(&self) -> Option<u32> {
+        (self.headers.len() as u32)
+            .checked_sub(1)
+            .map(|last| self.start + last)
+    }
+
+    /// Height of the block with `block_hash`, if it is in the chain
+    pub fn height(&self, block_hash: &BlockHash) -> Option<u32> {
+        self.heights.get(block_hash).copied()
+    }
+
+    /// The header at `height`
+    pub fn header(&self, height: u32) -> Option<&Header> {
+        let index = height.checked_sub(self.start)?;
+        self.headers.get(index as usize)
+    }
+
+    /// Timestamp of the block at `height`, seconds since the Unix epoch
+    pub fn time(&self, height: u32) -> Option<u32> {
+        self.header(height).map(|header| header.time)
+    }
+}
+
+#[cfg(feature = "json")]
+mod json {
+    use bitcoin::block::{Header, Version};
+    use bitcoin::hashes::Hash;
+    use bitcoin::{BlockHash, CompactTarget, TxMerkleNode};
+    use serde::Deserialize;
+
+    use super::Headers;
+    use crate::Error;
+
+    /// The fields of `getblockheader` output needed to rebuild a header
+    #[derive(Deserialize)]
+    struct JsonHeader {
+        hash: BlockHash,
+        height: u32,
+        version: i32,
+        merkleroot: TxMerkleNode,
+        time: u32,
+        nonce: u32,
+        bits: String,
+        previousblockhash: Option<BlockHash>,
+    }
+
+    /// One JSON value of the input
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Value {
+        One(JsonHeader),
+        Many(Vec<JsonHeader>),
+    }
+
+    impl Headers {
+        /// Read the output of `getblockheader <hash> true` for consecutive
+        /// blocks
+        ///
+        /// Accepts single objects, arrays of objects, or a mix of both
+        /// separated by whitespace, in any order.
+        pub fn from_json<R: std::io::Read>(reader: R) -> Result<Self, Error> {
+            let mut json = Vec::new();
+            for value in serde_json::Deserializer::from_reader(reader).into_iter() {
+                match value? {
+                    Value::One(header) => json.push(header),
+                    Value::Many(headers) => json.extend(headers),
+                }
+            }
+            json.sort_by_key(|header| header.height);
+
+            let start = json.first().map_or(0, |header| header.height);
+            let mut headers = Vec::with_capacity(json.len());
+            for (height, header) in (start..).zip(json) {
+                let bits = u32::from_str_radix(&header.bits, 16)
+                    .map_err(|_| Error::HeaderChain { height })?;
+                let rebuilt = Header {
+                    version: Version::from_consensus(header.version),
+                    prev_blockhash: header.previousblockhash.unwrap_or(BlockHash::all_zeros()),
+                    merkle_root: header.merkleroot,
+                    time: header.time,
+                    bits: CompactTarget::from_consensus(bits),
+                    nonce: header.nonce,
+                };
+                if header.height != height || rebuilt.block_hash() != header.hash {
+                    return Err(Error::HeaderChain { height });
+                }
+                headers.push(rebuilt);
+            }
+
+            Self::new(start, headers)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::consensus::serialize;
+
+    use super::*;
+
+    fn chain(len: u32) -> Vec<Header> {
+        let mut headers = vec![bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header];
+        for time in 1..len {
+            let prev = headers.last().unwrap();
+            headers.push(Header {
+                prev_blockhash: prev.block_hash(),
+                time: prev.time + time,
+                ..*prev
+            });
+        }
+        headers
+    }
+
+    #[test]
+    fn binary_headers() {
+        let headers = chain(5);
+        let bytes: Vec<u8> = headers.iter().flat_map(serialize).collect();
+
+        let chain = Headers::from_reader(bytes.as_slice()).unwrap();
+        assert_eq!(chain.tip_height(), Some(4));
+        assert_eq!(chain.height(&headers[3].block_hash()), Some(3));
+        assert_eq!(chain.time(2), Some(headers[2].time));
+        assert_eq!(chain.time(5), None);
+
+        assert!(Headers::from_reader(&bytes[..200]).is_err());
+
+        let mut broken = headers.clone();
+        broken.swap(2, 3);
+        assert!(matches!(
+            Headers::new(0, broken),
+            Err(Error::HeaderChain { height: 2 })
+        ));
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_headers() {
+        let headers = chain(3);
+        let json = |height: usize| {
+            let header = &headers[height];
+            let prev = if height > 0 {
+                format!(r#","previousblockhash":"{}""#, header.prev_blockhash)
+            } else {
+                String::new()
+            };
+            format!(
+                r#"{{"hash":"{}","confirmations":1,"height":{height},"version":{},"merkleroot":"{}","time":{},"nonce":{},"bits":"{:08x}"{prev}}}"#,
+                header.block_hash(),
+                header.version.to_consensus(),
+                header.merkle_root,
+                header.time,
+                header.nonce,
+                header.bits.to_consensus(),
+            )
+        };
+        let text = format!("[{},{}]\n{}\n", json(2), json(0), json(1));
+
+        let chain = Headers::from_json(text.as_bytes()).unwrap();
+        assert_eq!(chain.tip_height(), Some(2));
+        assert_eq!(chain.height(&headers[2].block_hash()), Some(2));
+
+        let text = format!("{}\n{}", json(0), json(2));
+        assert!(Headers::from_json(text.as_bytes()).is_err());
+    }
+}
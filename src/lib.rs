@@ -8,34 +8,85 @@
 //! }
 //! ```
 
+use std::fmt;
 use std::fs::File;
 use std::io::{BufReader, Seek};
 use std::path::Path;
+use std::sync::Arc;
 
-use bitcoin::consensus::{Decodable, Encodable};
-use bitcoin::io::{Read, Write};
+use bitcoin::consensus::Decodable;
+use bitcoin::hashes::Hash;
+#[cfg(feature = "chainstate")]
+use bitcoin::hex::DisplayHex;
+use bitcoin::io::Read;
 use bitcoin::p2p::Magic;
 use bitcoin::{Address, BlockHash, OutPoint, ScriptBuf, Txid};
 use thiserror::Error;
 
+pub mod age;
+pub mod aggregate;
 pub mod amount;
+pub mod blocks;
+#[cfg(feature = "chainstate")]
+pub mod chainstate;
+pub mod coin;
 pub mod compact_size;
+#[cfg(feature = "descriptors")]
+pub mod descriptor;
+pub mod exposure;
+pub mod headers;
+pub mod pattern;
+pub mod recovery;
 pub mod script;
+pub mod snapshot;
+mod source;
+pub mod spend;
+pub mod top;
+pub mod undo;
+pub mod update;
+pub mod validate;
 pub mod var_int;
+pub mod watch;
+pub use aggregate::{Aggregator, Balance};
 pub use amount::Amount;
+pub use blocks::BlockFile;
+#[cfg(feature = "chainstate")]
+pub use chainstate::{Chainstate, ChainstateWriter};
+pub use coin::Coin;
 pub use compact_size::CompactSize;
+pub use headers::Headers;
+pub use recovery::{Recovery, Skipped};
 pub use script::Script;
+pub use snapshot::SnapshotWriter;
+pub use undo::{BlockUndo, UndoFile, UndoRecord};
+pub use update::{Change, Update};
+pub use validate::{validate, DumpInfo, Mismatch, Report};
 pub use var_int::VarInt;
+pub use watch::WatchList;
+
+use crate::coin::Code;
+use crate::source::Source;
 
 const SNAPSHOT_MAGIC: [u8; 5] = [b'u', b't', b'x', b'o', 0xff];
 
+/// Largest encoded size of a single dump entry
+const MAX_RECORD_SIZE: usize =
+    32 + 2 * CompactSize::MAX_SIZE + 3 * VarInt::MAX_SIZE + script::MAX_SCRIPT_SIZE;
+
 /// An unspent transaction output entry
-#[derive(Debug, Clone, PartialEq, Eq)]
+///
+/// Fields may be added in minor versions, so build one from
+/// [`TxOut::default`] outside this crate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct TxOut {
     /// The address form of the script public key
     pub address: Option<Address>,
     /// Value of the output, satoshis
     pub amount: Amount,
+    /// Timestamp of the block at `height`, when headers are supplied with
+    /// [`Dump::with_headers`]
+    pub block_time: Option<u32>,
     /// Block height where the transaction was confirmed
     pub height: u32,
     /// Whether the output is in the coinbase transaction of the block
@@ -44,6 +95,22 @@ pub struct TxOut {
     pub out_point: OutPoint,
     /// The script public key
     pub script_pubkey: ScriptBuf,
+    /// Set when `script_pubkey` is a placeholder rather than the original
+    /// script, as Bitcoin Core decodes it
+    pub script_anomaly: Option<script::Anomaly>,
+}
+
+/// The unspent outputs of a single transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnspentTx {
+    /// The transaction ID shared by every output
+    pub txid: Txid,
+    /// Block height where the transaction was confirmed
+    pub height: u32,
+    /// Whether this is the coinbase transaction of the block
+    pub is_coinbase: bool,
+    /// Output index, value and script public key of each unspent output
+    pub outputs: Vec<(u32, Amount, ScriptBuf)>,
 }
 
 /// The UTXO set dump parser helper struct
@@ -56,17 +123,40 @@ where
 {
     /// Optionally compute addresses using this Network
     address_network: Option<bitcoin::Network>,
+    /// Height of the snapshot base block, when headers are supplied
+    base_height: Option<u32>,
     /// The block hash of the chain tip when the UTXO set was exported
     pub block_hash: BlockHash,
+    /// Zero-based index of the next entry
+    coin: u64,
+    /// First error encountered while iterating
+    error: Option<Error>,
+    /// Chain used to check coin heights and look up block times
+    headers: Option<Arc<Headers>>,
+    /// The network recorded in dumps from Core 28.0 and later
+    pub network: Option<bitcoin::Network>,
     /// The data source for the dump
-    reader: R,
+    reader: Source<R>,
+    /// Resynchronise after damaged entries instead of stopping
+    recovery: Option<Recovery>,
+    /// Recovery from a damaged entry, set by [`Dump::with_recovery`] as it
+    /// needs a seekable reader
+    resync: Option<Resync<R>>,
+    /// Byte ranges skipped during recovery
+    skipped: Vec<Skipped>,
     /// Internal state tracking for non-legacy dump files
     state: State,
+    /// Sum of the amounts of the entries read so far
+    total_amount: Amount,
     /// Number of entries in the dump file
     pub utxo_set_size: u64,
 }
 
+/// Scans a dump forward from a damaged entry to the next plausible one
+type Resync<R> = fn(&mut Dump<R>, Error) -> Result<(), Error>;
+
 /// Internal state for non-legacy dumps
+#[derive(Clone)]
 enum State {
     /// Working through a list of out points for the same TXID
     HaveTxid {
@@ -77,6 +167,8 @@ enum State {
     NeedTxid,
     /// No state tracking needed
     Legacy,
+    /// Recovery reached the end of the data
+    Finished,
 }
 
 /// Whether to compute addresses while processing.
@@ -110,7 +202,7 @@ pub enum Error {
     /// Standard I/O Error
     #[error("I/O: {0}")]
     Io(#[from] std::io::Error),
-    #[error("Cannot detect network for legacy dump formats")]
+    #[error("Cannot detect network for legacy dump formats or the chainstate")]
     NetworkDetect,
     /// Network mismatch between specified and detected network
     #[error("Specified network ({specified}) does not match detected network ({detected})")]
@@ -124,6 +216,180 @@ pub enum Error {
     /// Unknown magic bytes in the dump file
     #[error("Unknown magic bytes: {0}")]
     UnknownMagic(#[from] bitcoin::p2p::UnknownMagicError),
+    /// Problem decoding a field of a dump entry
+    #[error(
+        "Decode {field} of coin {coin} at offset {offset}{}: {source}",
+        txid.map(|txid| format!(" (txid {txid})")).unwrap_or_default()
+    )]
+    Entry {
+        /// Byte offset of the start of the record
+        offset: u64,
+        /// Zero-based index of the coin in the dump
+        coin: u64,
+        /// The field being decoded
+        field: Field,
+        /// Transaction ID of the current group for non-legacy dumps
+        txid: Option<Txid>,
+        /// The underlying decoding problem
+        source: bitcoin::consensus::encode::Error,
+    },
+    /// A block does not build on the tip it is applied to
+    #[error("Block {block} does not extend tip {tip}")]
+    Disconnected { block: BlockHash, tip: BlockHash },
+    /// A block's transactions do not match its header
+    #[error("Block {0} does not match its merkle root")]
+    BadMerkleRoot(BlockHash),
+    /// A block spends, or disconnecting it removes, a coin that is not
+    /// unspent
+    #[error("Coin {out_point} needed by block {block} is missing")]
+    MissingInput {
+        out_point: OutPoint,
+        block: BlockHash,
+    },
+    /// A block to disconnect is not the tip
+    #[error("Block {block} is not the tip {tip}")]
+    NotTip { block: BlockHash, tip: BlockHash },
+    /// Undo data does not fit the block being disconnected
+    #[error("Undo data does not fit block {0}")]
+    UndoMismatch(BlockHash),
+    /// The genesis block cannot be disconnected
+    #[error("Block {0} at height 0 cannot be disconnected")]
+    DisconnectGenesis(BlockHash),
+    /// A block needed to reach the target height was not supplied
+    #[error("Block {0} not found")]
+    MissingBlock(BlockHash),
+    /// No undo data was supplied for a block to disconnect
+    #[error("No undo data for block {0}")]
+    MissingUndo(BlockHash),
+    /// The snapshot is not at the block an update starts from
+    #[error("Snapshot is at block {found}, expected {expected}")]
+    BaseMismatch {
+        expected: BlockHash,
+        found: BlockHash,
+    },
+    /// A header does not link to the one before it or its stated hash
+    #[error("Header at height {height} is inconsistent with the chain")]
+    HeaderChain { height: u32 },
+    /// The snapshot base block is not among the supplied headers
+    #[error("Snapshot base block {0} is not in the headers")]
+    UnknownBase(BlockHash),
+    /// The operation needs headers supplied with [`Dump::with_headers`]
+    #[error("Headers are required")]
+    NoHeaders,
+    /// Problem parsing JSON input
+    #[cfg(feature = "json")]
+    #[error("JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    /// Problem reading the chainstate database
+    #[cfg(feature = "chainstate")]
+    #[error("LevelDB: {0}")]
+    LevelDb(#[from] rusty_leveldb::Status),
+    /// Problem decoding a chainstate record
+    #[cfg(feature = "chainstate")]
+    #[error("Decode chainstate record {}: {source}", key.as_hex())]
+    ChainstateRecord {
+        /// Database key of the record
+        key: Vec<u8>,
+        /// The underlying decoding problem
+        source: bitcoin::consensus::encode::Error,
+    },
+    /// The chainstate has no best block, so it is empty or a flush was
+    /// interrupted
+    #[cfg(feature = "chainstate")]
+    #[error("Chainstate has no best block")]
+    NoBestBlock,
+    /// An output descriptor could not be parsed or derived
+    #[cfg(feature = "descriptors")]
+    #[error("Invalid descriptor {descriptor}: {reason}")]
+    Descriptor {
+        /// The descriptor as given
+        descriptor: String,
+        /// What is wrong with it
+        reason: String,
+    },
+    /// A script pattern could not be parsed
+    #[error("Invalid script pattern {pattern}: {reason}")]
+    Pattern {
+        /// The pattern as given
+        pattern: String,
+        /// What is wrong with it
+        reason: String,
+    },
+}
+
+/// Fields of a dump entry, for error reporting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Field {
+    /// Transaction ID starting a group of out points
+    Txid,
+    /// Number of out points in a transaction group
+    VoutCount,
+    /// Output index within a transaction group
+    Vout,
+    /// Full out point of a legacy dump entry
+    OutPoint,
+    /// Combined height and coinbase flag
+    Code,
+    /// Compressed amount
+    Amount,
+    /// Compressed script public key
+    Script,
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Field::Txid => "txid",
+            Field::VoutCount => "vout count",
+            Field::Vout => "vout",
+            Field::OutPoint => "out point",
+            Field::Code => "code",
+            Field::Amount => "amount",
+            Field::Script => "script",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Position of the record being decoded, attached to decoding errors
+struct Record {
+    offset: u64,
+    coin: u64,
+    txid: Option<Txid>,
+}
+
+impl Record {
+    /// Decode one field of the record from `bytes[*pos..]`, annotating any
+    /// failure
+    ///
+    /// Advances `pos` past the field.
+    fn decode<T, F>(
+        &self,
+        bytes: &[u8],
+        pos: &mut usize,
+        field: Field,
+        decode: F,
+    ) -> Result<T, Error>
+    where
+        F: FnOnce(&[u8]) -> Result<(T, usize), bitcoin::consensus::encode::Error>,
+    {
+        let (value, consumed) =
+            decode(&bytes[*pos..]).map_err(|source| self.error(field, source))?;
+        *pos += consumed;
+        Ok(value)
+    }
+
+    /// Annotate a decoding failure with the record position
+    fn error(&self, field: Field, source: bitcoin::consensus::encode::Error) -> Error {
+        Error::Entry {
+            offset: self.offset,
+            coin: self.coin,
+            field,
+            txid: self.txid,
+            source,
+        }
+    }
 }
 
 impl<R> Dump<R>
@@ -137,7 +403,7 @@ where
         reader.read_exact(&mut possible_magic)?;
 
         // Snapshot from Core 28.0 or later starts with magic bytes
-        let (state, address_network) = if possible_magic == SNAPSHOT_MAGIC {
+        let (state, address_network, network) = if possible_magic == SNAPSHOT_MAGIC {
             let version = u16::consensus_decode(&mut reader)?;
             if version != 2 {
                 return Err(Error::UnknownVersion(version));
@@ -161,7 +427,7 @@ where
                 }
             };
 
-            (state, address_network)
+            (state, address_network, Some(network))
         } else {
             reader.rewind()?;
             let state = State::Legacy;
@@ -173,22 +439,288 @@ where
                 }
             };
 
-            (state, address_network)
+            (state, address_network, None)
         };
 
         let block_hash = BlockHash::consensus_decode(&mut reader)?;
         let utxo_set_size = u64::consensus_decode(&mut reader)?;
+        let position = reader.stream_position()?;
 
         Ok(Self {
             address_network,
+            base_height: None,
             block_hash,
-            reader,
+            coin: 0,
+            error: None,
+            headers: None,
+            network,
+            reader: Source::new(reader, position),
+            recovery: None,
+            resync: None,
+            skipped: Vec::new(),
             state,
+            total_amount: Amount::ZERO,
             utxo_set_size,
         })
     }
 }
 
+impl<R> Dump<R>
+where
+    R: Read,
+{
+    /// Check coins against a header chain and attach their block times
+    ///
+    /// Fails with [`Error::UnknownBase`] if the snapshot base block is not in
+    /// the chain. Afterwards, a coin younger than the base block is a decoding
+    /// error and every [`TxOut`] carries the time of its block.
+    pub fn with_headers(mut self, headers: impl Into<Arc<Headers>>) -> Result<Self, Error> {
+        let headers = headers.into();
+        let base_height = headers
+            .height(&self.block_hash)
+            .ok_or(Error::UnknownBase(self.block_hash))?;
+        self.base_height = Some(base_height);
+        self.headers = Some(headers);
+
+        Ok(self)
+    }
+
+    /// Height of the snapshot base block, known once headers are supplied
+    pub fn base_height(&self) -> Option<u32> {
+        self.base_height
+    }
+
+    /// The error that stopped iteration, if any
+    ///
+    /// The `Iterator` implementation ends at the first decoding problem unless
+    /// recovery is enabled with [`Dump::with_recovery`]. Use this method
+    /// afterwards to distinguish a complete dump from a damaged one, or call
+    /// [`Dump::try_next`] directly.
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Sum of the amounts of the entries read so far
+    ///
+    /// Entries are rejected when an amount or this running total exceeds
+    /// [`Amount::MAX_MONEY`].
+    pub fn total_amount(&self) -> Amount {
+        self.total_amount
+    }
+
+    /// Decode the entry at the current position into `tx_out`
+    fn read_entry(&mut self, tx_out: &mut TxOut) -> Result<(), Error> {
+        self.read_entry_if(tx_out, &mut |_| true).map(|_| ())
+    }
+
+    /// Decode the entry at the current position into `tx_out` if `filter`
+    /// accepts its compressed script
+    ///
+    /// A rejected entry is skipped without decompressing the script, leaving
+    /// `tx_out` unspecified, and `Ok(false)` is returned.
+    fn read_entry_if(
+        &mut self,
+        tx_out: &mut TxOut,
+        filter: &mut dyn FnMut(&[u8]) -> bool,
+    ) -> Result<bool, Error> {
+        let mut record = Record {
+            offset: self.reader.position(),
+            coin: self.coin,
+            txid: None,
+        };
+
+        let bytes = self.reader.fill(MAX_RECORD_SIZE)?;
+        let mut pos = 0;
+
+        let (out_point, state) = match self.state {
+            State::HaveTxid {
+                txid,
+                out_points_remaining,
+            } => {
+                record.txid = Some(txid);
+                let vout = record.decode(bytes, &mut pos, Field::Vout, decode_vout)?;
+                let out_points_remaining = out_points_remaining.saturating_sub(1);
+                let state = if out_points_remaining == 0 {
+                    State::NeedTxid
+                } else {
+                    State::HaveTxid {
+                        txid,
+                        out_points_remaining,
+                    }
+                };
+
+                (OutPoint::new(txid, vout), state)
+            }
+            State::NeedTxid => {
+                let txid = record.decode(bytes, &mut pos, Field::Txid, decode_txid)?;
+                record.txid = Some(txid);
+                let vout_count = record.decode(
+                    bytes,
+                    &mut pos,
+                    Field::VoutCount,
+                    CompactSize::decode_from_slice,
+                )?;
+                let out_points_remaining = u64::from(vout_count).saturating_sub(1);
+                let vout = record.decode(bytes, &mut pos, Field::Vout, decode_vout)?;
+                let state = if out_points_remaining > 0 {
+                    State::HaveTxid {
+                        txid,
+                        out_points_remaining,
+                    }
+                } else {
+                    State::NeedTxid
+                };
+
+                (OutPoint::new(txid, vout), state)
+            }
+            State::Legacy => {
+                let out_point =
+                    record.decode(bytes, &mut pos, Field::OutPoint, decode_out_point)?;
+                (out_point, State::Legacy)
+            }
+            State::Finished => unreachable!("no entries after recovery finished"),
+        };
+
+        let code = record.decode(bytes, &mut pos, Field::Code, Code::decode_from_slice)?;
+        if self
+            .base_height
+            .is_some_and(|base_height| code.height > base_height)
+        {
+            return Err(record.error(
+                Field::Code,
+                bitcoin::consensus::encode::Error::ParseFailed("height above snapshot base"),
+            ));
+        }
+
+        let amount = record.decode(bytes, &mut pos, Field::Amount, Amount::decode_from_slice)?;
+        if !amount.is_money_range() {
+            return Err(record.error(
+                Field::Amount,
+                bitcoin::consensus::encode::Error::ParseFailed("amount exceeds MAX_MONEY"),
+            ));
+        }
+        let total_amount = self.total_amount.checked_add_money(amount).ok_or_else(|| {
+            record.error(
+                Field::Amount,
+                bitcoin::consensus::encode::Error::ParseFailed("total amount exceeds MAX_MONEY"),
+            )
+        })?;
+
+        if !filter(&bytes[pos..]) {
+            record.decode(bytes, &mut pos, Field::Script, |bytes| {
+                script::compressed_len(bytes).map(|len| ((), len))
+            })?;
+            self.reader
+                .skip(pos)
+                .map_err(|source| record.error(Field::Script, source.into()))?;
+            self.state = state;
+            self.total_amount = total_amount;
+            self.coin += 1;
+            return Ok(false);
+        }
+
+        let mut script_bytes = std::mem::take(&mut tx_out.script_pubkey).into_bytes();
+        let decoded = record.decode(bytes, &mut pos, Field::Script, |bytes| {
+            script::decode_slice_into(bytes, &mut script_bytes)
+        });
+        tx_out.script_pubkey = ScriptBuf::from_bytes(script_bytes);
+        let script_anomaly = decoded?;
+
+        // An oversized script extends past the buffered record
+        self.reader
+            .skip(pos)
+            .map_err(|source| record.error(Field::Script, source.into()))?;
+        self.state = state;
+        self.total_amount = total_amount;
+
+        tx_out.address = self.address_network.and_then(|network| {
+            Address::from_script(tx_out.script_pubkey.as_script(), network).ok()
+        });
+        tx_out.amount = amount;
+        tx_out.block_time = self
+            .headers
+            .as_ref()
+            .and_then(|headers| headers.time(code.height));
+        tx_out.height = code.height;
+        tx_out.is_coinbase = code.is_coinbase;
+        tx_out.out_point = out_point;
+        tx_out.script_anomaly = script_anomaly;
+
+        self.coin += 1;
+
+        Ok(true)
+    }
+
+    /// Decode the next entry, reporting any problem
+    ///
+    /// Returns `Ok(None)` once all `utxo_set_size` entries have been read.
+    pub fn try_next(&mut self) -> Result<Option<TxOut>, Error> {
+        let mut tx_out = TxOut::default();
+        Ok(self.read_into(&mut tx_out)?.then_some(tx_out))
+    }
+
+    /// Decode the next entry into an existing [`TxOut`]
+    ///
+    /// The capacity of the previous script public key is reused, avoiding an
+    /// allocation per entry when the same `TxOut` is passed on every call.
+    /// Returns `Ok(false)` once all `utxo_set_size` entries have been read.
+    /// The contents of `tx_out` are unspecified after an error.
+    pub fn read_into(&mut self, tx_out: &mut TxOut) -> Result<bool, Error> {
+        self.read_into_if(tx_out, &mut |_| true)
+    }
+
+    /// Decode the next entry whose compressed script `filter` accepts into
+    /// `tx_out`, skipping the others without decompressing their scripts
+    pub(crate) fn read_into_if(
+        &mut self,
+        tx_out: &mut TxOut,
+        filter: &mut dyn FnMut(&[u8]) -> bool,
+    ) -> Result<bool, Error> {
+        loop {
+            if self.coin >= self.utxo_set_size || matches!(self.state, State::Finished) {
+                return Ok(false);
+            }
+
+            match self.read_entry_if(tx_out, filter) {
+                Ok(true) => return Ok(true),
+                Ok(false) => {}
+                Err(error) => match self.resync {
+                    Some(resync) => resync(self, error)?,
+                    None => return Err(error),
+                },
+            }
+        }
+    }
+
+    /// Group the remaining entries by transaction
+    ///
+    /// Consecutive entries sharing a TXID are collected into a single
+    /// [`UnspentTx`]. This matches the grouping stored in non-legacy dumps and
+    /// the out point ordering of legacy dumps.
+    pub fn transactions(self) -> Transactions<R> {
+        Transactions {
+            dump: self,
+            next: None,
+        }
+    }
+}
+
+impl<R> Dump<R>
+where
+    R: Read + Seek,
+{
+    /// Resynchronise after damaged entries instead of stopping
+    ///
+    /// When an entry fails to decode, the dump is scanned forward for the next
+    /// plausible record according to `recovery`. Each skipped byte range is
+    /// available from [`Dump::skipped`].
+    pub fn with_recovery(mut self, recovery: Recovery) -> Self {
+        self.recovery = Some(recovery);
+        self.resync = Some(Self::resync);
+        self
+    }
+}
+
 impl Dump<BufReader<File>> {
     /// Opens a UTXO set dump from a file path
     pub fn new(path: impl AsRef<Path>, compute_addresses: ComputeAddresses) -> Result<Self, Error> {
@@ -212,106 +744,150 @@ where
     type Item = TxOut;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let out_point = match self.state {
-            State::HaveTxid {
-                txid,
-                out_points_remaining,
-            } => {
-                let vout = u64::from(CompactSize::consensus_decode(&mut self.reader).ok()?) as u32;
-                let out_points_remaining = out_points_remaining.saturating_sub(1);
-                if out_points_remaining == 0 {
-                    self.state = State::NeedTxid;
-                } else {
-                    self.state = State::HaveTxid {
-                        txid,
-                        out_points_remaining,
-                    };
-                }
+        if self.error.is_some() {
+            return None;
+        }
 
-                OutPoint::new(txid, vout)
+        match self.try_next() {
+            Ok(tx_out) => tx_out,
+            Err(e) => {
+                self.error = Some(e);
+                None
             }
-            State::NeedTxid => {
-                let txid = Txid::consensus_decode(&mut self.reader).ok()?;
-                let out_points_remaining =
-                    u64::from(CompactSize::consensus_decode(&mut self.reader).ok()?)
-                        .saturating_sub(1);
-                let vout = u64::from(CompactSize::consensus_decode(&mut self.reader).ok()?) as u32;
-                if out_points_remaining > 0 {
-                    self.state = State::HaveTxid {
-                        txid,
-                        out_points_remaining,
-                    };
-                }
+        }
+    }
+}
 
-                OutPoint::new(txid, vout)
-            }
-            State::Legacy => OutPoint::consensus_decode(&mut self.reader).ok()?,
-        };
+/// Iterator over the [`UnspentTx`] entries of a [`Dump`]
+///
+/// Created by [`Dump::transactions`].
+pub struct Transactions<R>
+where
+    R: Read,
+{
+    /// The underlying dump parser
+    dump: Dump<R>,
+    /// First entry of the next transaction, already read from the dump
+    next: Option<TxOut>,
+}
 
-        let code = Code::consensus_decode(&mut self.reader).ok()?;
+impl<R> Transactions<R>
+where
+    R: Read,
+{
+    /// The error that stopped iteration, if any
+    ///
+    /// See [`Dump::error`].
+    pub fn error(&self) -> Option<&Error> {
+        self.dump.error()
+    }
+}
 
-        let amount = Amount::consensus_decode(&mut self.reader).ok()?;
+impl<R> Iterator for Transactions<R>
+where
+    R: Read,
+{
+    type Item = UnspentTx;
 
-        let script_buf = Script::consensus_decode(&mut self.reader)
-            .ok()?
-            .into_inner();
+    fn next(&mut self) -> Option<Self::Item> {
+        let first = self.next.take().or_else(|| self.dump.next())?;
 
-        let address = self
-            .address_network
-            .and_then(|network| Address::from_script(script_buf.as_script(), network).ok());
+        let mut unspent_tx = UnspentTx {
+            txid: first.out_point.txid,
+            height: first.height,
+            is_coinbase: first.is_coinbase,
+            outputs: vec![(first.out_point.vout, first.amount, first.script_pubkey)],
+        };
 
-        Some(TxOut {
-            address,
-            amount,
-            height: code.height,
-            is_coinbase: code.is_coinbase,
-            out_point,
-            script_pubkey: script_buf,
-        })
+        for tx_out in self.dump.by_ref() {
+            if tx_out.out_point.txid != unspent_tx.txid {
+                self.next = Some(tx_out);
+                break;
+            }
+            unspent_tx
+                .outputs
+                .push((tx_out.out_point.vout, tx_out.amount, tx_out.script_pubkey));
+        }
+
+        Some(unspent_tx)
     }
 }
 
-#[derive(Debug)]
-struct Code {
-    height: u32,
-    is_coinbase: bool,
+/// Decode a TXID from the start of `bytes`
+fn decode_txid(bytes: &[u8]) -> Result<(Txid, usize), bitcoin::consensus::encode::Error> {
+    let txid = bytes
+        .first_chunk::<32>()
+        .ok_or_else(|| bitcoin::io::Error::from(bitcoin::io::ErrorKind::UnexpectedEof))?;
+    Ok((Txid::from_byte_array(*txid), 32))
 }
 
-impl Encodable for Code {
-    fn consensus_encode<W: Write + ?Sized>(
-        &self,
-        writer: &mut W,
-    ) -> Result<usize, bitcoin::io::Error> {
-        let code = self.height * 2 + u32::from(self.is_coinbase);
-        let var_int = VarInt::from(code);
+/// Decode a non-legacy output index from the start of `bytes`
+fn decode_vout(bytes: &[u8]) -> Result<(u32, usize), bitcoin::consensus::encode::Error> {
+    let (vout, consumed) = CompactSize::decode_from_slice(bytes)?;
+    let vout = u32::try_from(u64::from(vout))
+        .map_err(|_| bitcoin::consensus::encode::Error::ParseFailed("vout out of range"))?;
+    Ok((vout, consumed))
+}
 
-        var_int.consensus_encode(writer)
-    }
+/// Append the bytes ordering `out_point` the way Bitcoin Core's database and
+/// dumps do: the txid, then the output index as a [`VarInt`]
+pub(crate) fn extend_out_point_key(key: &mut Vec<u8>, out_point: &OutPoint) {
+    key.extend_from_slice(out_point.txid.as_byte_array());
+
+    let mut vout = [0; VarInt::MAX_SIZE];
+    let len = VarInt::from(out_point.vout)
+        .encode_to_slice(&mut vout)
+        .expect("buffer fits any VarInt");
+    key.extend_from_slice(&vout[..len]);
 }
 
-impl Decodable for Code {
-    fn consensus_decode<R: Read + ?Sized>(
-        reader: &mut R,
-    ) -> Result<Self, bitcoin::consensus::encode::Error> {
-        let var_int = VarInt::consensus_decode(reader)?;
-        let code = u32::try_from(u64::from(var_int))
-            .map_err(|_| bitcoin::consensus::encode::Error::ParseFailed("invalid cast to u32"))?;
-
-        Ok(Code {
-            height: code >> 1,
-            is_coinbase: (code & 0x01) == 1,
-        })
-    }
+/// Decode a legacy out point from the start of `bytes`
+fn decode_out_point(bytes: &[u8]) -> Result<(OutPoint, usize), bitcoin::consensus::encode::Error> {
+    let (txid, consumed) = decode_txid(bytes)?;
+    let vout = bytes[consumed..]
+        .first_chunk::<4>()
+        .ok_or_else(|| bitcoin::io::Error::from(bitcoin::io::ErrorKind::UnexpectedEof))?;
+    Ok((OutPoint::new(txid, u32::from_le_bytes(*vout)), consumed + 4))
 }
 
 #[cfg(test)]
 mod test {
     use std::io::Cursor;
 
-    use super::{ComputeAddresses, Dump, Network, TxOut};
+    use bitcoin::consensus::Encodable;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{BlockHash, OutPoint, ScriptBuf, Txid};
 
-    const DUMP_27_0: &[u8] = include_bytes!("../test/dump-27_0.dat");
-    const DUMP_28_0: &[u8] = include_bytes!("../test/dump-28_0.dat");
+    use super::coin::Code;
+    use super::script::Anomaly;
+    use super::{
+        Amount, ComputeAddresses, Dump, Error, Field, Headers, Network, SnapshotWriter, TxOut,
+        VarInt,
+    };
+
+    pub(crate) const DUMP_27_0: &[u8] = include_bytes!("../test/dump-27_0.dat");
+    pub(crate) const DUMP_28_0: &[u8] = include_bytes!("../test/dump-28_0.dat");
+
+    /// The 28.0 test dump re-encoded with the script of the coin at each index
+    /// replaced by `script`, where it returns one
+    pub(crate) fn rewrite_scripts(
+        compute_addresses: ComputeAddresses,
+        mut script: impl FnMut(usize) -> Option<ScriptBuf>,
+    ) -> Dump<Cursor<Vec<u8>>> {
+        let dump = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        let mut writer =
+            SnapshotWriter::new(&mut out, bitcoin::Network::Signet, dump.block_hash).unwrap();
+        for (i, mut tx_out) in dump.enumerate() {
+            if let Some(script_pubkey) = script(i) {
+                tx_out.script_pubkey = script_pubkey;
+            }
+            writer.add(tx_out).unwrap();
+        }
+        writer.finish().unwrap();
+
+        Dump::from_reader(Cursor::new(out.into_inner()), compute_addresses).unwrap()
+    }
 
     // The 100th tx out in the dump files
     fn validate_tx_out(tx_out: TxOut) {
@@ -330,7 +906,7 @@ mod test {
         )
         .expect("Load Dump 27.0");
 
-        let last_tx_out = dump.into_iter().skip(99).next().expect("100th tx out");
+        let last_tx_out = dump.into_iter().nth(99).expect("100th tx out");
 
         validate_tx_out(last_tx_out);
     }
@@ -341,8 +917,257 @@ mod test {
         let dump = Dump::from_reader(reader, ComputeAddresses::Yes(Network::Detect))
             .expect("Load Dump 28.0");
 
-        let last_tx_out = dump.into_iter().skip(99).next().expect("100th tx out");
+        let last_tx_out = dump.into_iter().nth(99).expect("100th tx out");
 
         validate_tx_out(last_tx_out);
     }
+
+    #[test]
+    fn transactions_match_across_versions() {
+        let dump_27 = Dump::from_reader(Cursor::new(DUMP_27_0), ComputeAddresses::No)
+            .expect("Load Dump 27.0");
+        let dump_28 = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No)
+            .expect("Load Dump 28.0");
+        let utxo_set_size = dump_28.utxo_set_size;
+
+        let txs_27: Vec<_> = dump_27.transactions().collect();
+        let txs_28: Vec<_> = dump_28.transactions().collect();
+        assert_eq!(txs_27, txs_28);
+
+        let outputs: usize = txs_28.iter().map(|tx| tx.outputs.len()).sum();
+        assert_eq!(outputs as u64, utxo_set_size);
+    }
+
+    #[test]
+    fn read_into_reuses_entry() {
+        let expected: Vec<_> = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No)
+            .expect("Load Dump 28.0")
+            .collect();
+
+        let mut dump = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No)
+            .expect("Load Dump 28.0");
+        let mut tx_out = TxOut::default();
+        let mut count = 0;
+        while dump.read_into(&mut tx_out).expect("read entry") {
+            assert_eq!(tx_out, expected[count]);
+            count += 1;
+        }
+        assert_eq!(count, expected.len());
+    }
+
+    /// Build a legacy dump with one OP_TRUE output per out point
+    ///
+    /// Returns the dump bytes and the offset of each record.
+    fn legacy_dump(out_points: &[OutPoint]) -> (Vec<u8>, Vec<u64>) {
+        // Uncompressed one-byte script: OP_TRUE
+        let entries: Vec<_> = out_points
+            .iter()
+            .map(|out_point| (*out_point, vec![0x07, 0x51]))
+            .collect();
+        legacy_dump_with_scripts(&entries)
+    }
+
+    /// Build a legacy dump from out points and compressed scripts
+    fn legacy_dump_with_scripts(entries: &[(OutPoint, Vec<u8>)]) -> (Vec<u8>, Vec<u64>) {
+        let mut legacy = Vec::new();
+        let mut offsets = Vec::new();
+        BlockHash::all_zeros()
+            .consensus_encode(&mut legacy)
+            .unwrap();
+        (entries.len() as u64)
+            .consensus_encode(&mut legacy)
+            .unwrap();
+        for (i, (out_point, script)) in entries.iter().enumerate() {
+            offsets.push(legacy.len() as u64);
+            out_point.consensus_encode(&mut legacy).unwrap();
+            Code {
+                height: 7,
+                is_coinbase: false,
+            }
+            .consensus_encode(&mut legacy)
+            .unwrap();
+            Amount::new(1000 * (i as u64 + 1))
+                .consensus_encode(&mut legacy)
+                .unwrap();
+            legacy.extend_from_slice(script);
+        }
+
+        (legacy, offsets)
+    }
+
+    #[test]
+    fn transactions_group_out_points() {
+        let txids = [
+            Txid::from_byte_array([1; 32]),
+            Txid::from_byte_array([2; 32]),
+        ];
+        let (legacy, _) = legacy_dump(&[
+            OutPoint::new(txids[0], 0),
+            OutPoint::new(txids[0], 3),
+            OutPoint::new(txids[1], 1),
+        ]);
+
+        let dump = Dump::from_reader(Cursor::new(legacy), ComputeAddresses::No).unwrap();
+        let txs: Vec<_> = dump.transactions().collect();
+
+        assert_eq!(txs.len(), 2);
+        assert_eq!(txs[0].txid, txids[0]);
+        assert_eq!(txs[0].height, 7);
+        let vouts: Vec<_> = txs[0].outputs.iter().map(|(vout, ..)| *vout).collect();
+        assert_eq!(vouts, [0, 3]);
+        assert_eq!(txs[1].outputs.len(), 1);
+        assert_eq!(txs[1].outputs[0].1, Amount::new(3000));
+    }
+
+    #[test]
+    fn decode_error_position() {
+        let txid = Txid::from_byte_array([1; 32]);
+        let (mut legacy, offsets) = legacy_dump(&[
+            OutPoint::new(txid, 0),
+            OutPoint::new(txid, 1),
+            OutPoint::new(txid, 2),
+        ]);
+        // Cut the last script short
+        legacy.pop();
+
+        let mut dump = Dump::from_reader(Cursor::new(legacy), ComputeAddresses::No).unwrap();
+        assert_eq!(dump.by_ref().count(), 2);
+        match dump.error() {
+            Some(Error::Entry {
+                offset,
+                coin,
+                field,
+                txid,
+                ..
+            }) => {
+                assert_eq!(*offset, offsets[2]);
+                assert_eq!(*coin, 2);
+                assert_eq!(*field, Field::Script);
+                assert_eq!(*txid, None);
+            }
+            other => panic!("unexpected error: {other:?}"),
+        }
+
+        // Non-legacy dumps report the TXID of the current group
+        let truncated = &DUMP_28_0[..DUMP_28_0.len() - 1];
+        let mut dump = Dump::from_reader(Cursor::new(truncated), ComputeAddresses::No).unwrap();
+        assert!(dump.by_ref().count() < 100);
+        assert!(matches!(
+            dump.error(),
+            Some(Error::Entry { txid: Some(_), .. })
+        ));
+
+        let mut dump = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        assert_eq!(dump.by_ref().count(), 100);
+        assert!(dump.error().is_none());
+    }
+
+    #[test]
+    fn oversized_script_placeholder() {
+        let txid = Txid::from_byte_array([1; 32]);
+        // Larger than the read buffer
+        let size = 2_000_000_u64;
+        let mut oversized = vec![0; VarInt::MAX_SIZE];
+        let len = VarInt::new(size + 6)
+            .encode_to_slice(&mut oversized)
+            .unwrap();
+        oversized.truncate(len);
+        oversized.resize(len + size as usize, 0xab);
+
+        let (legacy, _) = legacy_dump_with_scripts(&[
+            (OutPoint::new(txid, 0), oversized),
+            (OutPoint::new(txid, 1), vec![0x07, 0x51]),
+        ]);
+        let mut dump = Dump::from_reader(Cursor::new(legacy), ComputeAddresses::No).unwrap();
+        let tx_outs: Vec<_> = dump.by_ref().collect();
+
+        assert!(dump.error().is_none());
+        assert_eq!(tx_outs.len(), 2);
+        assert_eq!(tx_outs[0].script_pubkey.as_bytes(), [0x6a]);
+        assert_eq!(tx_outs[0].script_anomaly, Some(Anomaly::Oversized { size }));
+        assert_eq!(tx_outs[1].script_pubkey.as_bytes(), [0x51]);
+        assert_eq!(tx_outs[1].script_anomaly, None);
+    }
+
+    #[test]
+    fn reject_amounts_beyond_max_money() {
+        let mut dump = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        let total = dump.by_ref().fold(Amount::ZERO, |total, tx_out| {
+            total.checked_add(tx_out.amount).unwrap()
+        });
+        assert_eq!(dump.total_amount(), total);
+
+        // 21M BTC plus one satoshi
+        let mut too_much = Vec::new();
+        Amount::new(Amount::MAX_MONEY.to_sat() + 1)
+            .consensus_encode(&mut too_much)
+            .unwrap();
+
+        let txid = Txid::from_byte_array([1; 32]);
+        let (mut legacy, offsets) = legacy_dump(&[OutPoint::new(txid, 0)]);
+        let amount = offsets[0] as usize + 36 + 1;
+        legacy.splice(amount..amount + 1, too_much);
+
+        let mut dump = Dump::from_reader(Cursor::new(legacy), ComputeAddresses::No).unwrap();
+        assert_eq!(dump.by_ref().count(), 0);
+        assert!(matches!(
+            dump.error(),
+            Some(Error::Entry {
+                field: Field::Amount,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn check_heights_against_headers() {
+        // Regtest headers up to height 100
+        let mut headers = vec![bitcoin::constants::genesis_block(bitcoin::Network::Regtest).header];
+        for _ in 0..100 {
+            let prev = headers.last().unwrap();
+            headers.push(bitcoin::block::Header {
+                prev_blockhash: prev.block_hash(),
+                time: prev.time + 600,
+                ..*prev
+            });
+        }
+        let headers = Headers::new(0, headers).unwrap();
+
+        // The coins of the test dump, which reach height 100, on a base block
+        // at `height`
+        let snapshot = |height| {
+            let block_hash = headers.header(height).unwrap().block_hash();
+            let mut out = Cursor::new(Vec::new());
+            let mut writer =
+                SnapshotWriter::new(&mut out, bitcoin::Network::Regtest, block_hash).unwrap();
+            for tx_out in Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap() {
+                writer.add(tx_out).unwrap();
+            }
+            writer.finish().unwrap();
+            Dump::from_reader(Cursor::new(out.into_inner()), ComputeAddresses::No).unwrap()
+        };
+
+        let mut dump = snapshot(100).with_headers(headers.clone()).unwrap();
+        assert_eq!(dump.base_height(), Some(100));
+        for tx_out in dump.by_ref() {
+            assert_eq!(tx_out.block_time, headers.time(tx_out.height));
+        }
+        assert!(dump.error().is_none());
+
+        let mut dump = snapshot(50).with_headers(headers.clone()).unwrap();
+        assert!(dump.by_ref().all(|tx_out| tx_out.height <= 50));
+        assert!(matches!(
+            dump.error(),
+            Some(Error::Entry {
+                field: Field::Code,
+                ..
+            })
+        ));
+
+        let dump = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        assert!(matches!(
+            dump.with_headers(headers),
+            Err(Error::UnknownBase(_))
+        ));
+    }
 }
@@ -0,0 +1,461 @@
+//! Searching for scripts by opcode template
+//!
+//! A [`Pattern`] is a whitespace-separated sequence of tokens, each matching
+//! one instruction of a script public key, except `...`:
+//!
+//! - `OP_CHECKSIG`: that opcode. Names are those of `rust-bitcoin` and the
+//!   aliases of Bitcoin Core, such as `OP_0`, `OP_TRUE`, `OP_2` and
+//!   `OP_CHECKLOCKTIMEVERIFY`
+//! - `OP_*`: any opcode other than a data push
+//! - `<20>`, `<20-65>`, `<*>`: a data push of that many bytes, of a length in
+//!   the range, or of any length
+//! - `<0x6a24aa21a9ed>`: a push of exactly these bytes
+//! - `N`, `N>3`, `N>=3`, `N<3`, `N<=3`, `N=3`: a small number `OP_0` to
+//!   `OP_16`, compared with its value
+//! - `*`: any instruction
+//! - `...`: any number of instructions, including none
+//!
+//! Patterns match whole scripts, so `... N>3 OP_CHECKMULTISIG ...` finds the
+//! scripts with a multisig of more than three keys anywhere. Scripts that do
+//! not decode into instructions never match.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use bitcoin::hex::FromHex;
+use bitcoin::io::Read;
+use bitcoin::opcodes::all::{OP_PUSHBYTES_0, OP_PUSHNUM_1, OP_PUSHNUM_16};
+use bitcoin::opcodes::Opcode;
+use bitcoin::script::Instruction;
+use bitcoin::Script;
+
+use crate::script::MAX_SCRIPT_SIZE;
+use crate::spend::Tally;
+use crate::{Dump, Error, TxOut};
+
+/// Bitcoin Core names of opcodes that `rust-bitcoin` names differently
+const ALIASES: [(&str, u8); 8] = [
+    ("OP_0", 0x00),
+    ("OP_FALSE", 0x00),
+    ("OP_1NEGATE", 0x4f),
+    ("OP_TRUE", 0x51),
+    ("OP_NOP2", 0xb1),
+    ("OP_CHECKLOCKTIMEVERIFY", 0xb1),
+    ("OP_NOP3", 0xb2),
+    ("OP_CHECKSEQUENCEVERIFY", 0xb2),
+];
+
+/// A script template with wildcards
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    /// The pattern as given
+    source: String,
+    tokens: Vec<Token>,
+}
+
+/// One token of a pattern
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    /// That opcode
+    Op(u8),
+    /// Any opcode other than a data push
+    AnyOp,
+    /// A data push with a length in the range
+    Push(RangeInclusive<usize>),
+    /// A push of these bytes
+    Data(Vec<u8>),
+    /// `OP_0` to `OP_16` with a value in the range
+    Number(RangeInclusive<u8>),
+    /// Any instruction
+    Any,
+    /// Any number of instructions
+    Rest,
+}
+
+/// An instruction of a script being matched
+struct Element<'a> {
+    /// The first byte of the instruction
+    opcode: u8,
+    /// The pushed bytes of a data push
+    data: Option<&'a [u8]>,
+}
+
+impl Pattern {
+    /// Whether `script` matches the pattern
+    pub fn is_match(&self, script: &Script) -> bool {
+        glob(&self.tokens, script)
+    }
+
+    /// Iterate over the entries of `dump` whose script matches the pattern
+    pub fn matches<R>(&self, dump: Dump<R>) -> Matches<'_, R>
+    where
+        R: Read,
+    {
+        Matches {
+            dump,
+            error: None,
+            pattern: self,
+        }
+    }
+
+    /// Count the entries of `dump` whose script matches the pattern and their
+    /// value
+    ///
+    /// The entries themselves are not kept; iterate over [`Pattern::matches`]
+    /// to process them.
+    pub fn search<R>(&self, mut dump: Dump<R>) -> Result<Search, Error>
+    where
+        R: Read,
+    {
+        let mut search = Search::default();
+        let mut tx_out = TxOut::default();
+        while dump.read_into(&mut tx_out)? {
+            search.total.add(tx_out.amount);
+            if self.is_match(&tx_out.script_pubkey) {
+                search.matched.add(tx_out.amount);
+            }
+        }
+
+        Ok(search)
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = s
+            .split_whitespace()
+            .map(|token| {
+                Token::parse(token).ok_or_else(|| Error::Pattern {
+                    pattern: s.to_owned(),
+                    reason: format!("unknown token {token}"),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            source: s.to_owned(),
+            tokens,
+        })
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Token {
+    fn parse(token: &str) -> Option<Self> {
+        match token {
+            "..." => return Some(Self::Rest),
+            "*" => return Some(Self::Any),
+            "OP_*" => return Some(Self::AnyOp),
+            _ => {}
+        }
+        if let Some(inner) = token.strip_prefix('<').and_then(|t| t.strip_suffix('>')) {
+            return parse_push(inner);
+        }
+        if let Some(comparison) = token.strip_prefix('N') {
+            return parse_number(comparison);
+        }
+
+        let opcode = ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == token)
+            .map(|&(_, opcode)| opcode)
+            .or_else(|| {
+                let n: u8 = token.strip_prefix("OP_")?.parse().ok()?;
+                (1..=16).contains(&n).then(|| n + OP_PUSHNUM_1.to_u8() - 1)
+            })
+            .or_else(|| (0..=u8::MAX).find(|&byte| Opcode::from(byte).to_string() == token))?;
+        Some(Self::Op(opcode))
+    }
+
+    fn matches(&self, element: &Element) -> bool {
+        match self {
+            Self::Op(opcode) => element.opcode == *opcode,
+            Self::AnyOp => element.data.is_none(),
+            Self::Push(lengths) => element.data.is_some_and(|d| lengths.contains(&d.len())),
+            Self::Data(bytes) => element.data == Some(bytes.as_slice()),
+            Self::Number(values) => {
+                small_number(element.opcode).is_some_and(|n| values.contains(&n))
+            }
+            Self::Any | Self::Rest => true,
+        }
+    }
+}
+
+/// The inside of `<...>`: a length, a range of lengths, `*` or hex bytes
+fn parse_push(inner: &str) -> Option<Token> {
+    if inner == "*" {
+        return Some(Token::Push(0..=MAX_SCRIPT_SIZE));
+    }
+    if let Some(hex) = inner.strip_prefix("0x") {
+        return Vec::from_hex(hex).ok().map(Token::Data);
+    }
+    let lengths = match inner.split_once('-') {
+        Some((min, max)) => min.parse().ok()?..=max.parse().ok()?,
+        None => {
+            let length = inner.parse().ok()?;
+            length..=length
+        }
+    };
+
+    (!lengths.is_empty()).then_some(Token::Push(lengths))
+}
+
+/// What follows `N`: nothing or a comparison with a number up to 16
+fn parse_number(comparison: &str) -> Option<Token> {
+    let parse = |n: &str| n.parse::<u8>().ok().filter(|&n| n <= 16);
+    let values = if comparison.is_empty() {
+        0..=16
+    } else if let Some(n) = comparison.strip_prefix(">=") {
+        parse(n)?..=16
+    } else if let Some(n) = comparison.strip_prefix("<=") {
+        0..=parse(n)?
+    } else if let Some(n) = comparison.strip_prefix('>') {
+        parse(n)?.checked_add(1)?..=16
+    } else if let Some(n) = comparison.strip_prefix('<') {
+        0..=parse(n)?.checked_sub(1)?
+    } else if let Some(n) = comparison.strip_prefix('=') {
+        let n = parse(n)?;
+        n..=n
+    } else {
+        return None;
+    };
+
+    (!values.is_empty()).then_some(Token::Number(values))
+}
+
+/// The value pushed by `OP_0` to `OP_16`
+fn small_number(opcode: u8) -> Option<u8> {
+    if opcode == OP_PUSHBYTES_0.to_u8() {
+        Some(0)
+    } else if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&opcode) {
+        Some(opcode - OP_PUSHNUM_1.to_u8() + 1)
+    } else {
+        None
+    }
+}
+
+/// Whether the instructions of `script` match `tokens`, with `...` matching
+/// any run of instructions
+///
+/// Instructions are decoded as they are matched, and only the position after
+/// the last `...` is kept for backtracking.
+fn glob(tokens: &[Token], script: &Script) -> bool {
+    let bytes = script.as_bytes();
+    let mut t = 0;
+    let mut instructions = script.instruction_indices();
+    // The last `...` seen and the instructions after those it has consumed
+    let mut rest = None;
+    loop {
+        let mut next = instructions.clone();
+        let element = match next.next() {
+            None => break,
+            Some(Err(_)) => return false,
+            Some(Ok((index, instruction))) => Element {
+                opcode: bytes[index],
+                data: match instruction {
+                    Instruction::PushBytes(data) => Some(data.as_bytes()),
+                    Instruction::Op(_) => None,
+                },
+            },
+        };
+        match tokens.get(t) {
+            Some(Token::Rest) => {
+                rest = Some((t, instructions.clone()));
+                t += 1;
+            }
+            Some(token) if token.matches(&element) => {
+                t += 1;
+                instructions = next;
+            }
+            _ => match &mut rest {
+                // Let the `...` consume one more instruction and retry
+                Some((rest_t, rest_instructions)) => {
+                    rest_instructions.next();
+                    t = *rest_t + 1;
+                    instructions = rest_instructions.clone();
+                }
+                None => return false,
+            },
+        }
+    }
+
+    tokens[t..].iter().all(|token| *token == Token::Rest)
+}
+
+/// The result of [`Pattern::search`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Search {
+    /// Count and value of the matching entries
+    pub matched: Tally,
+    /// Count and value of every entry searched
+    pub total: Tally,
+}
+
+/// Iterator over the entries of a dump whose script matches a pattern
+pub struct Matches<'a, R>
+where
+    R: Read,
+{
+    /// The dump being searched
+    dump: Dump<R>,
+    /// First error encountered while iterating
+    error: Option<Error>,
+    /// The pattern to match
+    pattern: &'a Pattern,
+}
+
+impl<R> Matches<'_, R>
+where
+    R: Read,
+{
+    /// The error that stopped iteration, if any
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Find the next matching entry, distinguishing the end of the dump from
+    /// errors
+    pub fn try_next(&mut self) -> Result<Option<TxOut>, Error> {
+        while let Some(tx_out) = self.dump.try_next()? {
+            if self.pattern.is_match(&tx_out.script_pubkey) {
+                return Ok(Some(tx_out));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Give back the dump, positioned after the last entry read
+    pub fn into_inner(self) -> Dump<R> {
+        self.dump
+    }
+}
+
+impl<R> Iterator for Matches<'_, R>
+where
+    R: Read,
+{
+    type Item = TxOut;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.try_next() {
+            Ok(item) => item,
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::hex::DisplayHex;
+    use bitcoin::script::Builder;
+    use bitcoin::{opcodes, PubkeyHash, ScriptBuf, WPubkeyHash};
+
+    use super::*;
+    use crate::test::DUMP_28_0;
+    use crate::ComputeAddresses;
+
+    fn pattern(s: &str) -> Pattern {
+        s.parse().unwrap()
+    }
+
+    fn multisig(required: u8, keys: u8) -> ScriptBuf {
+        let mut builder = Builder::new().push_int(required.into());
+        for _ in 0..keys {
+            builder = builder.push_slice([2; 33]);
+        }
+        builder
+            .push_int(keys.into())
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script()
+    }
+
+    #[test]
+    fn match_templates() {
+        let p2pkh = ScriptBuf::new_p2pkh(&PubkeyHash::all_zeros());
+        let p2wpkh = ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros());
+        let mut p2pkh_extra = p2pkh.clone();
+        p2pkh_extra.push_opcode(opcodes::all::OP_NOP);
+
+        let template = "OP_DUP OP_HASH160 <20> OP_EQUALVERIFY OP_CHECKSIG";
+        assert!(pattern(template).is_match(&p2pkh));
+        assert!(!pattern(template).is_match(&p2pkh_extra));
+        assert!(pattern(&format!("{template} OP_*")).is_match(&p2pkh_extra));
+        assert!(!pattern(&format!("{template} OP_*")).is_match(&p2pkh));
+        assert!(pattern(&format!("{template} ...")).is_match(&p2pkh));
+
+        assert!(pattern("OP_0 <20>").is_match(&p2wpkh));
+        assert!(pattern("N=0 <1-32>").is_match(&p2wpkh));
+        assert!(pattern("* <*>").is_match(&p2wpkh));
+        assert!(!pattern("OP_* <*>").is_match(&p2wpkh));
+        assert!(pattern(&format!("OP_FALSE <0x{}>", "00".repeat(20))).is_match(&p2wpkh));
+
+        let wide = "... N>3 OP_CHECKMULTISIG ...";
+        assert!(pattern(wide).is_match(&multisig(1, 4)));
+        assert!(!pattern(wide).is_match(&multisig(2, 3)));
+        assert!(pattern("OP_1 ... OP_3 OP_CHECKMULTISIG").is_match(&multisig(1, 3)));
+        assert!(pattern("N<=2 <33> ... OP_CHECKMULTISIG").is_match(&multisig(2, 3)));
+
+        // Truncated push
+        let broken = ScriptBuf::from_bytes(vec![0x4c]);
+        assert!(!pattern("...").is_match(&broken));
+        let mut broken = p2pkh.to_bytes();
+        broken.push(0x4c);
+        let broken = ScriptBuf::from_bytes(broken);
+        assert!(!pattern(&format!("{template} ...")).is_match(&broken));
+        assert!(pattern("").is_match(&ScriptBuf::new()));
+
+        for bad in ["OP_BOGUS", "<>", "<5-2>", "N>16", "N<0", "<0xzz>", "OP_17"] {
+            assert!(
+                matches!(bad.parse::<Pattern>(), Err(Error::Pattern { .. })),
+                "{bad}"
+            );
+        }
+        assert_eq!(pattern(template).to_string(), template);
+    }
+
+    #[test]
+    fn search_dump() {
+        let dump = || Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        let all: Vec<_> = dump().collect();
+
+        let p2wpkh = pattern("OP_0 <20>");
+        let search = p2wpkh.search(dump()).unwrap();
+        let expected: Vec<_> = all
+            .iter()
+            .filter(|tx_out| tx_out.script_pubkey.is_p2wpkh())
+            .cloned()
+            .collect();
+        assert_eq!(p2wpkh.matches(dump()).collect::<Vec<_>>(), expected);
+        assert_eq!(search.matched.utxo_count, expected.len() as u64);
+        assert_eq!(
+            search.matched.amount.to_sat(),
+            expected.iter().map(|t| t.amount.to_sat()).sum::<u64>()
+        );
+        assert_eq!(search.total.utxo_count, 100);
+
+        let script = &all[3].script_pubkey;
+        let program = script.as_bytes()[2..].to_lower_hex_string();
+        let exact = pattern(&format!("OP_0 <0x{program}>"));
+        let matches: Vec<_> = exact.matches(dump()).collect();
+        assert!(matches.iter().all(|tx_out| tx_out.script_pubkey == *script));
+        assert!(!matches.is_empty());
+    }
+}
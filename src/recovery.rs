@@ -0,0 +1,221 @@
+//! Resynchronisation after damaged dump entries
+
+use std::io::Seek;
+
+use bitcoin::io::Read;
+
+use crate::{extend_out_point_key, Dump, Error, State, TxOut};
+
+/// Upper bound on the outputs of a single transaction
+///
+/// A block of maximum weight filled with minimal 9-byte outputs.
+const MAX_VOUT_COUNT: u64 = 4_000_000 / (4 * 9);
+
+/// Number of consecutive plausible records required to resume decoding
+const LOOKAHEAD: usize = 3;
+
+/// Plausibility limits used to find the next record after a decode failure
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    /// Height of the snapshot base block; no coin may be younger
+    pub base_height: u32,
+    /// Largest number of out points accepted in a transaction group
+    pub max_vout_count: u64,
+}
+
+impl Recovery {
+    /// Recovery limits for a snapshot taken at `base_height`
+    pub fn new(base_height: u32) -> Self {
+        Self {
+            base_height,
+            max_vout_count: MAX_VOUT_COUNT,
+        }
+    }
+
+    /// Whether a decoded entry looks like genuine data
+    fn accepts(&self, tx_out: &TxOut, state: &State) -> bool {
+        let vout_count_ok = match state {
+            State::HaveTxid {
+                out_points_remaining,
+                ..
+            } => *out_points_remaining < self.max_vout_count,
+            _ => true,
+        };
+
+        vout_count_ok
+            && tx_out.script_anomaly.is_none()
+            && u64::from(tx_out.out_point.vout) < self.max_vout_count
+            && (1..=self.base_height).contains(&tx_out.height)
+    }
+}
+
+/// A byte range skipped while recovering from a damaged entry
+#[derive(Debug)]
+pub struct Skipped {
+    /// Byte offset of the damaged record
+    pub start: u64,
+    /// Byte offset where decoding resumed, or the end of the data
+    pub end: u64,
+    /// The problem that triggered recovery
+    pub error: Error,
+}
+
+impl<R> Dump<R>
+where
+    R: Read + Seek,
+{
+    /// Byte ranges skipped so far in recovery mode
+    pub fn skipped(&self) -> &[Skipped] {
+        &self.skipped
+    }
+
+    /// Scan forward from a damaged record to the next plausible one
+    ///
+    /// Leaves the reader positioned at the start of that record, or marks the
+    /// dump finished when the data runs out first.
+    pub(crate) fn resync(&mut self, error: Error) -> Result<(), Error> {
+        let (Some(recovery), Error::Entry { offset: start, .. }) = (self.recovery.clone(), &error)
+        else {
+            return Err(error);
+        };
+        let start = *start;
+
+        let fresh_state = match self.state {
+            State::Legacy => State::Legacy,
+            _ => State::NeedTxid,
+        };
+        let coin = self.coin;
+        let total_amount = self.total_amount;
+        let len = self.reader.len()?;
+
+        // Out of data at a record boundary: the coins lost in earlier skipped
+        // ranges account for the shortfall, otherwise the dump is truncated
+        if start >= len {
+            if self.skipped.is_empty() {
+                return Err(error);
+            }
+            self.state = State::Finished;
+            return Ok(());
+        }
+
+        let mut candidate = start + 1;
+        while candidate < len {
+            self.reader.seek_to(candidate)?;
+            self.state = fresh_state.clone();
+            let plausible = self.plausible(&recovery, len);
+            self.coin = coin;
+            self.total_amount = total_amount;
+
+            if plausible {
+                self.reader.seek_to(candidate)?;
+                self.state = fresh_state;
+                break;
+            }
+            candidate += 1;
+        }
+
+        if candidate >= len {
+            candidate = len;
+            self.state = State::Finished;
+        }
+
+        log::warn!("Skipped bytes {start}..{candidate} after: {error}");
+        self.skipped.push(Skipped {
+            start,
+            end: candidate,
+            error,
+        });
+
+        Ok(())
+    }
+
+    /// Whether the next records decode and pass the recovery checks
+    ///
+    /// Dumps list out points in ascending database key order, so consecutive
+    /// records must be increasing as well.
+    fn plausible(&mut self, recovery: &Recovery, len: u64) -> bool {
+        let mut previous = Vec::new();
+        let mut key = Vec::new();
+        let mut tx_out = TxOut::default();
+        for _ in 0..LOOKAHEAD {
+            if self.reader.position() == len {
+                return true;
+            }
+            match self.read_entry(&mut tx_out) {
+                Ok(()) if recovery.accepts(&tx_out, &self.state) => {
+                    key.clear();
+                    extend_out_point_key(&mut key, &tx_out.out_point);
+                    if !previous.is_empty() && previous >= key {
+                        return false;
+                    }
+                    std::mem::swap(&mut previous, &mut key);
+                }
+                _ => return false,
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use crate::test::DUMP_28_0;
+    use crate::{ComputeAddresses, Dump, Recovery};
+
+    #[test]
+    fn skips_damaged_record() {
+        // Record offsets of the undamaged dump
+        let mut dump = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        let mut offsets = Vec::new();
+        let mut clean = Vec::new();
+        loop {
+            offsets.push(dump.reader.position());
+            match dump.try_next().unwrap() {
+                Some(tx_out) => clean.push(tx_out),
+                None => break,
+            }
+        }
+
+        // Overwrite the code of the 50th coin, the start of a txid group, with
+        // an overlong VarInt
+        let mut damaged = DUMP_28_0.to_vec();
+        let code = offsets[49] as usize + 32 + 1 + 1;
+        damaged[code..code + 10].copy_from_slice(&[0xff; 10]);
+
+        let mut dump = Dump::from_reader(Cursor::new(&damaged), ComputeAddresses::No)
+            .unwrap()
+            .with_recovery(Recovery::new(100));
+        let tx_outs: Vec<_> = dump.by_ref().collect();
+
+        assert!(dump.error().is_none());
+        assert_eq!(tx_outs.len(), 99);
+        assert_eq!(tx_outs[..49], clean[..49]);
+        assert_eq!(tx_outs[49..], clean[50..]);
+
+        let skipped = dump.skipped();
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(skipped[0].start, offsets[49]);
+        assert_eq!(skipped[0].end, offsets[50]);
+    }
+
+    #[test]
+    fn truncated_at_record_boundary() {
+        let mut dump = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        for _ in 0..60 {
+            dump.try_next().unwrap();
+        }
+        let end = dump.reader.position() as usize;
+
+        // Nothing was skipped, so there is nothing to account for the
+        // missing coins
+        let mut dump = Dump::from_reader(Cursor::new(&DUMP_28_0[..end]), ComputeAddresses::No)
+            .unwrap()
+            .with_recovery(Recovery::new(100));
+        assert_eq!(dump.by_ref().count(), 60);
+        assert!(dump.error().is_some());
+        assert!(dump.skipped().is_empty());
+    }
+}
@@ -1,93 +1,466 @@
 use bitcoin::consensus::encode::Error;
-use bitcoin::consensus::Decodable;
-use bitcoin::hashes::Hash;
+use bitcoin::consensus::{Decodable, Encodable};
 use bitcoin::script::ScriptBuf;
-use bitcoin::{opcodes, PubkeyHash, PublicKey, ScriptHash};
+use bitcoin::{opcodes, PublicKey};
 
 const NUM_SPECIAL_SCRIPTS: usize = 6;
-const MAX_SCRIPT_SIZE: usize = 10_000;
+pub(crate) const MAX_SCRIPT_SIZE: usize = 10_000;
 
 use crate::VarInt;
 
 /// Wrapper to enable script decompression
 #[derive(Debug)]
-pub struct Script(ScriptBuf);
+pub struct Script {
+    /// The decompressed script
+    script: ScriptBuf,
+    /// Set when the script is not the original script public key
+    anomaly: Option<Anomaly>,
+}
+
+/// Compressed scripts that Bitcoin Core does not decompress verbatim
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Anomaly {
+    /// The script exceeds `MAX_SCRIPT_SIZE` bytes
+    ///
+    /// Like Bitcoin Core, the payload is skipped and the script replaced with
+    /// a single unspendable `OP_RETURN`.
+    Oversized {
+        /// Length of the skipped script
+        size: u64,
+    },
+    /// An uncompressed pay-to-pubkey script whose x-coordinate is not on the
+    /// curve
+    ///
+    /// Like Bitcoin Core, the script decodes as empty. The compressed form is
+    /// kept so the entry can be encoded again unchanged.
+    InvalidPublicKey {
+        /// Size code of the compressed script, `0x04` or `0x05`
+        code: u8,
+        /// The key in compressed form: `code - 2` followed by the x-coordinate
+        compressed: [u8; 33],
+    },
+}
 
 impl Script {
+    /// Pair a script with the anomaly it was decoded with
+    pub fn from_parts(script: ScriptBuf, anomaly: Option<Anomaly>) -> Self {
+        Self { script, anomaly }
+    }
+
     /// Reveal the inner script buffer
     pub fn into_inner(self) -> ScriptBuf {
-        self.0
+        self.script
+    }
+
+    /// Whether the script differs from the original script public key
+    pub fn anomaly(&self) -> Option<Anomaly> {
+        self.anomaly
+    }
+
+    /// Decode from the start of `bytes`, returning the script and the number
+    /// of bytes consumed
+    ///
+    /// For an [`Anomaly::Oversized`] script the count includes the skipped
+    /// payload and may exceed the length of `bytes`.
+    pub fn decode_from_slice(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let mut script = Vec::new();
+        let (anomaly, consumed) = decode_slice_into(bytes, &mut script)?;
+        let script = Script {
+            script: ScriptBuf::from_bytes(script),
+            anomaly,
+        };
+        Ok((script, consumed))
+    }
+}
+
+impl From<ScriptBuf> for Script {
+    fn from(script: ScriptBuf) -> Self {
+        Self::from_parts(script, None)
+    }
+}
+
+impl Encodable for Script {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        match self.anomaly {
+            Some(Anomaly::InvalidPublicKey { code, compressed }) => {
+                writer.write_all(&[code])?;
+                writer.write_all(&compressed[1..])?;
+                Ok(compressed.len())
+            }
+            _ => compress(&self.script, writer),
+        }
     }
 }
 
 impl Decodable for Script {
     fn consensus_decode<R: bitcoin::io::Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
-        let mut size = u64::from(VarInt::consensus_decode(reader)?) as usize;
-
-        match size {
-            0x00 => {
-                // P2PKH
-                let mut bytes = [0; 20];
-                reader.read_exact(&mut bytes)?;
-                let pubkey_hash =
-                    PubkeyHash::from_slice(&bytes).map_err(|_| Error::ParseFailed("HASH-160"))?;
-                Ok(Script(ScriptBuf::new_p2pkh(&pubkey_hash)))
-            }
-            0x01 => {
-                // P2SH
-                let mut bytes = [0; 20];
-                reader.read_exact(&mut bytes)?;
-                let script_hash =
-                    ScriptHash::from_slice(&bytes).map_err(|_| Error::ParseFailed("HASH-160"))?;
-                Ok(Script(ScriptBuf::new_p2sh(&script_hash)))
-            }
-            0x02 | 0x03 => {
-                // P2PK (compressed)
-                let mut bytes = [0; 32];
-                reader.read_exact(&mut bytes)?;
-
-                let mut script_bytes = Vec::with_capacity(35);
-                script_bytes.push(opcodes::all::OP_PUSHBYTES_33.to_u8());
-                script_bytes.push(size as u8);
-                script_bytes.extend_from_slice(&bytes);
-                script_bytes.push(opcodes::all::OP_CHECKSIG.to_u8());
-
-                Ok(Script(ScriptBuf::from(script_bytes)))
+        let mut bytes = Vec::new();
+        let anomaly = decode_into(reader, &mut bytes)?;
+        Ok(Script {
+            script: ScriptBuf::from_bytes(bytes),
+            anomaly,
+        })
+    }
+}
+
+/// Decompress a script into an existing buffer
+///
+/// The buffer is cleared first, so its capacity can be reused across calls.
+pub fn decode_into<R: bitcoin::io::Read + ?Sized>(
+    reader: &mut R,
+    bytes: &mut Vec<u8>,
+) -> Result<Option<Anomaly>, Error> {
+    bytes.clear();
+    let size = u64::from(VarInt::consensus_decode(reader)?);
+
+    match payload(size)? {
+        Payload::Special(len) => {
+            let mut payload = [0; 32];
+            reader.read_exact(&mut payload[..len])?;
+            Ok(decompress_special(size as u8, &payload[..len], bytes))
+        }
+        Payload::Raw(len) => {
+            bytes.resize(len, 0);
+            reader.read_exact(bytes)?;
+            Ok(None)
+        }
+        Payload::Oversized(len) => {
+            let mut skipped = 0;
+            let mut discard = [0; 4096];
+            while skipped < len {
+                let n = (len - skipped).min(discard.len() as u64) as usize;
+                reader.read_exact(&mut discard[..n])?;
+                skipped += n as u64;
             }
-            0x04 | 0x05 => {
-                // P2PK (uncompressed)
-                let mut bytes = [0; 32];
-                reader.read_exact(&mut bytes)?;
+            bytes.push(opcodes::all::OP_RETURN.to_u8());
+            Ok(Some(Anomaly::Oversized { size: len }))
+        }
+    }
+}
+
+/// Decompress a script from the start of a slice into an existing buffer
+///
+/// Returns any anomaly and the number of bytes consumed. For an
+/// [`Anomaly::Oversized`] script the count includes the skipped payload and
+/// may exceed the length of `bytes`. The buffer is cleared first, so its
+/// capacity can be reused across calls.
+pub fn decode_slice_into(
+    bytes: &[u8],
+    script: &mut Vec<u8>,
+) -> Result<(Option<Anomaly>, usize), Error> {
+    script.clear();
+    let (size, header_len) = VarInt::decode_from_slice(bytes)?;
+    let size = u64::from(size);
+    let eof = || {
+        Error::from(bitcoin::io::Error::from(
+            bitcoin::io::ErrorKind::UnexpectedEof,
+        ))
+    };
+
+    match payload(size)? {
+        Payload::Special(len) => {
+            let payload = bytes.get(header_len..header_len + len).ok_or_else(eof)?;
+            let anomaly = decompress_special(size as u8, payload, script);
+            Ok((anomaly, header_len + len))
+        }
+        Payload::Raw(len) => {
+            let payload = bytes.get(header_len..header_len + len).ok_or_else(eof)?;
+            script.extend_from_slice(payload);
+            Ok((None, header_len + len))
+        }
+        Payload::Oversized(len) => {
+            let consumed = usize::try_from(len)
+                .ok()
+                .and_then(|len| len.checked_add(header_len))
+                .ok_or_else(eof)?;
+            script.push(opcodes::all::OP_RETURN.to_u8());
+            Ok((Some(Anomaly::Oversized { size: len }), consumed))
+        }
+    }
+}
 
-                let mut compressed_pubkey_bytes = Vec::with_capacity(33);
-                compressed_pubkey_bytes.push((size - 2) as u8);
-                compressed_pubkey_bytes.extend_from_slice(&bytes);
+/// Length of the compressed script at the start of `bytes`, without
+/// decompressing it
+///
+/// Like [`decode_slice_into`], the length of an oversized script includes the
+/// skipped payload and may exceed the length of `bytes`.
+pub(crate) fn compressed_len(bytes: &[u8]) -> Result<usize, Error> {
+    let (size, header_len) = VarInt::decode_from_slice(bytes)?;
+    let len = match payload(u64::from(size))? {
+        Payload::Special(len) | Payload::Raw(len) => len,
+        Payload::Oversized(len) => {
+            usize::try_from(len).map_err(|_| Error::ParseFailed("script size too large"))?
+        }
+    };
+    let consumed = header_len + len;
+    if consumed > bytes.len() && len <= MAX_SCRIPT_SIZE {
+        return Err(Error::from(bitcoin::io::Error::from(
+            bitcoin::io::ErrorKind::UnexpectedEof,
+        )));
+    }
 
-                let compressed_pubkey = PublicKey::from_slice(&compressed_pubkey_bytes)
-                    .map_err(|_| Error::ParseFailed("parse public key"))?;
-                let inner_uncompressed = compressed_pubkey.inner.serialize_uncompressed();
+    Ok(consumed)
+}
 
-                let mut script_bytes = Vec::with_capacity(67);
-                script_bytes.push(opcodes::all::OP_PUSHBYTES_65.to_u8());
-                script_bytes.extend_from_slice(&inner_uncompressed);
-                script_bytes.push(opcodes::all::OP_CHECKSIG.to_u8());
+/// Layout of the bytes following the size code of a compressed script
+enum Payload {
+    /// Key or hash of one of the special script templates
+    Special(usize),
+    /// Script bytes stored verbatim
+    Raw(usize),
+    /// Script bytes to skip
+    Oversized(u64),
+}
 
-                Ok(Script(ScriptBuf::from(script_bytes)))
-            }
-            _ => {
-                size = size.saturating_sub(NUM_SPECIAL_SCRIPTS);
-                if size > MAX_SCRIPT_SIZE {
-                    Err(Error::OversizedVectorAllocation {
-                        requested: size,
-                        max: MAX_SCRIPT_SIZE,
-                    })
-                } else {
-                    let mut bytes = Vec::with_capacity(size);
-                    bytes.resize_with(size, || 0);
-                    reader.read_exact(&mut bytes)?;
-                    Ok(Script(ScriptBuf::from_bytes(bytes)))
-                }
+/// Determine the payload layout from the size code
+fn payload(size: u64) -> Result<Payload, Error> {
+    match size {
+        0x00 | 0x01 => Ok(Payload::Special(20)),
+        0x02..=0x05 => Ok(Payload::Special(32)),
+        // Bitcoin Core reads the size code as a 32-bit integer
+        _ if size > u64::from(u32::MAX) => Err(Error::ParseFailed("script size too large")),
+        _ => {
+            let len = size - NUM_SPECIAL_SCRIPTS as u64;
+            if len > MAX_SCRIPT_SIZE as u64 {
+                Ok(Payload::Oversized(len))
+            } else {
+                Ok(Payload::Raw(len as usize))
             }
         }
     }
 }
+
+/// Expand one of the special script templates
+///
+/// Returns an anomaly, leaving `bytes` empty, when the payload does not
+/// describe a valid script.
+fn decompress_special(size: u8, payload: &[u8], bytes: &mut Vec<u8>) -> Option<Anomaly> {
+    match size {
+        0x00 => {
+            // P2PKH
+            bytes.extend_from_slice(&[
+                opcodes::all::OP_DUP.to_u8(),
+                opcodes::all::OP_HASH160.to_u8(),
+                opcodes::all::OP_PUSHBYTES_20.to_u8(),
+            ]);
+            bytes.extend_from_slice(payload);
+            bytes.extend_from_slice(&[
+                opcodes::all::OP_EQUALVERIFY.to_u8(),
+                opcodes::all::OP_CHECKSIG.to_u8(),
+            ]);
+        }
+        0x01 => {
+            // P2SH
+            bytes.extend_from_slice(&[
+                opcodes::all::OP_HASH160.to_u8(),
+                opcodes::all::OP_PUSHBYTES_20.to_u8(),
+            ]);
+            bytes.extend_from_slice(payload);
+            bytes.push(opcodes::all::OP_EQUAL.to_u8());
+        }
+        0x02 | 0x03 => {
+            // P2PK (compressed)
+            bytes.push(opcodes::all::OP_PUSHBYTES_33.to_u8());
+            bytes.push(size);
+            bytes.extend_from_slice(payload);
+            bytes.push(opcodes::all::OP_CHECKSIG.to_u8());
+        }
+        _ => {
+            // P2PK (uncompressed)
+            let mut compressed_pubkey_bytes = [0; 33];
+            compressed_pubkey_bytes[0] = size - 2;
+            compressed_pubkey_bytes[1..].copy_from_slice(payload);
+
+            // Bitcoin Core leaves the script empty when the point is invalid
+            let Ok(compressed_pubkey) = PublicKey::from_slice(&compressed_pubkey_bytes) else {
+                return Some(Anomaly::InvalidPublicKey {
+                    code: size,
+                    compressed: compressed_pubkey_bytes,
+                });
+            };
+            let inner_uncompressed = compressed_pubkey.inner.serialize_uncompressed();
+
+            bytes.push(opcodes::all::OP_PUSHBYTES_65.to_u8());
+            bytes.extend_from_slice(&inner_uncompressed);
+            bytes.push(opcodes::all::OP_CHECKSIG.to_u8());
+        }
+    }
+
+    None
+}
+
+/// Compress a script, writing the size code and payload to `writer`
+pub(crate) fn compress<W: bitcoin::io::Write + ?Sized>(
+    script: &bitcoin::Script,
+    writer: &mut W,
+) -> Result<usize, bitcoin::io::Error> {
+    let bytes = script.as_bytes();
+
+    let special = if script.is_p2pkh() {
+        Some((0x00, &bytes[3..23]))
+    } else if script.is_p2sh() {
+        Some((0x01, &bytes[2..22]))
+    } else if bytes.len() == 35
+        && bytes[0] == opcodes::all::OP_PUSHBYTES_33.to_u8()
+        && bytes[34] == opcodes::all::OP_CHECKSIG.to_u8()
+        && (bytes[1] == 0x02 || bytes[1] == 0x03)
+    {
+        Some((bytes[1], &bytes[2..34]))
+    } else if bytes.len() == 67
+        && bytes[0] == opcodes::all::OP_PUSHBYTES_65.to_u8()
+        && bytes[66] == opcodes::all::OP_CHECKSIG.to_u8()
+        && bytes[1] == 0x04
+        && PublicKey::from_slice(&bytes[1..66]).is_ok()
+    {
+        Some((0x04 | (bytes[65] & 0x01), &bytes[2..34]))
+    } else {
+        None
+    };
+
+    match special {
+        Some((code, payload)) => {
+            writer.write_all(&[code])?;
+            writer.write_all(payload)?;
+            Ok(1 + payload.len())
+        }
+        None => {
+            let size = VarInt::from((bytes.len() + NUM_SPECIAL_SCRIPTS) as u64);
+            let len = size.consensus_encode(writer)?;
+            writer.write_all(bytes)?;
+            Ok(len + bytes.len())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::hashes::Hash;
+
+    use bitcoin::{PubkeyHash, ScriptHash};
+
+    use super::*;
+
+    #[test]
+    fn decompress_hash_scripts() {
+        let hash = [0xab; 20];
+
+        let mut compressed = vec![0x00];
+        compressed.extend_from_slice(&hash);
+        let script = Script::consensus_decode(&mut compressed.as_slice()).expect("decode");
+        let expected = ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array(hash));
+        assert_eq!(script.into_inner(), expected);
+
+        compressed[0] = 0x01;
+        let script = Script::consensus_decode(&mut compressed.as_slice()).expect("decode");
+        let expected = ScriptBuf::new_p2sh(&ScriptHash::from_byte_array(hash));
+        assert_eq!(script.into_inner(), expected);
+
+        let (script, consumed) = Script::decode_from_slice(&compressed).expect("decode");
+        assert_eq!(script.into_inner(), expected);
+        assert_eq!(consumed, 21);
+        assert!(Script::decode_from_slice(&compressed[..20]).is_err());
+    }
+
+    #[test]
+    fn skip_oversized_script() {
+        let size = MAX_SCRIPT_SIZE as u64 + 1;
+        let mut compressed = vec![0; VarInt::MAX_SIZE];
+        let header_len = VarInt::from(size + NUM_SPECIAL_SCRIPTS as u64)
+            .encode_to_slice(&mut compressed)
+            .expect("encode");
+        compressed.truncate(header_len);
+        compressed.resize(header_len + size as usize, 0xab);
+        // Next field
+        compressed.push(0x42);
+
+        let mut reader = compressed.as_slice();
+        let script = Script::consensus_decode(&mut reader).expect("decode");
+        assert_eq!(script.anomaly(), Some(Anomaly::Oversized { size }));
+        assert_eq!(
+            script.into_inner().as_bytes(),
+            [opcodes::all::OP_RETURN.to_u8()]
+        );
+        assert_eq!(reader, [0x42]);
+
+        // The slice decoder reports the skipped length even when truncated
+        let (script, consumed) =
+            Script::decode_from_slice(&compressed[..header_len]).expect("decode");
+        assert_eq!(script.anomaly(), Some(Anomaly::Oversized { size }));
+        assert_eq!(consumed, compressed.len() - 1);
+    }
+
+    #[test]
+    fn compression_round_trips() {
+        let compressed_key = [0x02; 33];
+        let uncompressed_key = PublicKey::from_slice(&[
+            0x03, 0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce,
+            0x87, 0x0b, 0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81,
+            0x5b, 0x16, 0xf8, 0x17, 0x98,
+        ])
+        .expect("generator point")
+        .inner
+        .serialize_uncompressed();
+
+        let mut p2pk_compressed = vec![opcodes::all::OP_PUSHBYTES_33.to_u8()];
+        p2pk_compressed.extend_from_slice(&compressed_key);
+        p2pk_compressed.push(opcodes::all::OP_CHECKSIG.to_u8());
+        let mut p2pk_uncompressed = vec![opcodes::all::OP_PUSHBYTES_65.to_u8()];
+        p2pk_uncompressed.extend_from_slice(&uncompressed_key);
+        p2pk_uncompressed.push(opcodes::all::OP_CHECKSIG.to_u8());
+
+        let cases = [
+            (
+                ScriptBuf::new_p2pkh(&PubkeyHash::from_byte_array([1; 20])),
+                21,
+            ),
+            (
+                ScriptBuf::new_p2sh(&ScriptHash::from_byte_array([2; 20])),
+                21,
+            ),
+            (ScriptBuf::from_bytes(p2pk_compressed), 33),
+            (ScriptBuf::from_bytes(p2pk_uncompressed), 33),
+            (ScriptBuf::from_bytes(vec![0x51]), 2),
+        ];
+
+        for (script, len) in cases {
+            let mut encoded = Vec::new();
+            Script::from(script.clone())
+                .consensus_encode(&mut encoded)
+                .expect("encode");
+            assert_eq!(encoded.len(), len, "{script:?}");
+
+            let decoded = Script::consensus_decode(&mut encoded.as_slice()).expect("decode");
+            assert_eq!(decoded.into_inner(), script);
+        }
+    }
+
+    #[test]
+    fn preserve_invalid_public_key() {
+        // x = 5 is not on secp256k1
+        let mut compressed = vec![0x05];
+        compressed.extend_from_slice(&[0; 31]);
+        compressed.push(0x05);
+
+        let (script, consumed) = Script::decode_from_slice(&compressed).expect("decode");
+        assert_eq!(consumed, 33);
+        assert!(script.script.is_empty());
+        let Some(Anomaly::InvalidPublicKey {
+            code,
+            compressed: key,
+        }) = script.anomaly()
+        else {
+            panic!("expected invalid public key");
+        };
+        assert_eq!(code, 0x05);
+        assert_eq!(key[0], 0x03);
+        assert_eq!(key[1..], compressed[1..]);
+
+        let mut encoded = Vec::new();
+        script.consensus_encode(&mut encoded).expect("encode");
+        assert_eq!(encoded, compressed);
+    }
+}
@@ -0,0 +1,145 @@
+//! Writer for the snapshot format of Bitcoin Core 28.0 and later
+
+use std::io::{Seek, SeekFrom, Write};
+
+use bitcoin::consensus::Encodable;
+use bitcoin::{BlockHash, Txid};
+
+use crate::{Coin, CompactSize, Error, TxOut, SNAPSHOT_MAGIC};
+
+/// Snapshot format version written
+const VERSION: u16 = 2;
+
+/// Writes [`TxOut`] entries as a snapshot that [`crate::Dump`] and
+/// `loadtxoutset` read
+///
+/// Entries should be added in the order Bitcoin Core dumps them, so each
+/// transaction forms a single group. The coin count in the header is filled in
+/// by [`SnapshotWriter::finish`].
+pub struct SnapshotWriter<W> {
+    /// Encoding buffer for a transaction group
+    buf: Vec<u8>,
+    /// Number of coins added so far
+    coins: u64,
+    /// Offset of the coin count in the header
+    count_position: u64,
+    /// Output indexes and coins of the current transaction group
+    group: Vec<(u32, Coin)>,
+    /// Transaction ID of the current group
+    txid: Option<Txid>,
+    /// The data destination
+    writer: W,
+}
+
+impl<W> SnapshotWriter<W>
+where
+    W: Write + Seek,
+{
+    /// Write the header of a snapshot of `network` taken at `block_hash`
+    pub fn new(
+        mut writer: W,
+        network: bitcoin::Network,
+        block_hash: BlockHash,
+    ) -> Result<Self, Error> {
+        let mut header = SNAPSHOT_MAGIC.to_vec();
+        VERSION.consensus_encode(&mut header)?;
+        network.magic().consensus_encode(&mut header)?;
+        block_hash.consensus_encode(&mut header)?;
+        let count_position = writer.stream_position()? + header.len() as u64;
+        0_u64.consensus_encode(&mut header)?;
+        writer.write_all(&header)?;
+
+        Ok(Self {
+            buf: Vec::new(),
+            coins: 0,
+            count_position,
+            group: Vec::new(),
+            txid: None,
+            writer,
+        })
+    }
+
+    /// Add an entry to the snapshot
+    pub fn add(&mut self, tx_out: TxOut) -> Result<(), Error> {
+        let txid = tx_out.out_point.txid;
+        if self.txid != Some(txid) {
+            self.write_group()?;
+            self.txid = Some(txid);
+        }
+
+        self.group.push((tx_out.out_point.vout, Coin::from(tx_out)));
+        self.coins += 1;
+
+        Ok(())
+    }
+
+    /// Write the last group, fill in the coin count and return it
+    pub fn finish(mut self) -> Result<u64, Error> {
+        self.write_group()?;
+
+        self.writer.seek(SeekFrom::Start(self.count_position))?;
+        self.writer.write_all(&self.coins.to_le_bytes())?;
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+
+        Ok(self.coins)
+    }
+
+    /// Write out the pending transaction group
+    fn write_group(&mut self) -> Result<(), Error> {
+        let Some(txid) = self.txid else {
+            return Ok(());
+        };
+
+        self.buf.clear();
+        txid.consensus_encode(&mut self.buf)?;
+        CompactSize::from(self.group.len() as u64).consensus_encode(&mut self.buf)?;
+        for (vout, coin) in self.group.drain(..) {
+            CompactSize::from(vout).consensus_encode(&mut self.buf)?;
+            coin.consensus_encode(&mut self.buf)?;
+        }
+        self.writer.write_all(&self.buf)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test::{DUMP_27_0, DUMP_28_0};
+    use crate::{ComputeAddresses, Dump};
+
+    #[test]
+    fn rewrite_legacy_dump() {
+        let dump = Dump::from_reader(Cursor::new(DUMP_27_0), ComputeAddresses::No).unwrap();
+        let block_hash = dump.block_hash;
+
+        let mut writer = SnapshotWriter::new(
+            Cursor::new(Vec::new()),
+            bitcoin::Network::Signet,
+            block_hash,
+        )
+        .unwrap();
+        for tx_out in dump {
+            writer.add(tx_out).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), 100);
+    }
+
+    #[test]
+    fn reproduce_dump() {
+        let dump = Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        let mut out = Cursor::new(Vec::new());
+        let mut writer =
+            SnapshotWriter::new(&mut out, dump.network.unwrap(), dump.block_hash).unwrap();
+        for tx_out in dump {
+            writer.add(tx_out).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert_eq!(out.into_inner(), DUMP_28_0);
+    }
+}
@@ -0,0 +1,129 @@
+use std::io::{Seek, SeekFrom};
+
+use bitcoin::io::Read;
+
+/// Size of the read buffer
+const BUFFER_SIZE: usize = 1 << 20;
+
+/// Buffered reader that tracks the byte offset into the dump
+///
+/// Entries are decoded directly from the buffered bytes rather than through
+/// many small reads.
+pub(crate) struct Source<R> {
+    /// The wrapped data source
+    inner: R,
+    /// Read buffer; `buf[start..end]` holds data not yet consumed
+    buf: Box<[u8]>,
+    /// Start of the unconsumed data in `buf`
+    start: usize,
+    /// End of the valid data in `buf`
+    end: usize,
+    /// Byte offset of `buf[start]` from the start of the dump
+    position: u64,
+}
+
+impl<R> Source<R> {
+    /// Wrap a reader that has already consumed `position` bytes
+    pub(crate) fn new(inner: R, position: u64) -> Self {
+        Self {
+            inner,
+            buf: vec![0; BUFFER_SIZE].into_boxed_slice(),
+            start: 0,
+            end: 0,
+            position,
+        }
+    }
+
+    /// Byte offset of the next read from the start of the dump
+    pub(crate) fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Mark `n` buffered bytes as read
+    ///
+    /// `n` must not exceed the length of the slice last returned by
+    /// [`Source::fill`].
+    pub(crate) fn consume(&mut self, n: usize) {
+        debug_assert!(n <= self.end - self.start);
+        self.start += n;
+        self.position += n as u64;
+    }
+}
+
+impl<R> Source<R>
+where
+    R: Read,
+{
+    /// Buffer at least `min` bytes, or everything left before the end of the
+    /// data, and return the unconsumed bytes
+    pub(crate) fn fill(&mut self, min: usize) -> bitcoin::io::Result<&[u8]> {
+        debug_assert!(min <= BUFFER_SIZE);
+        if self.end - self.start < min {
+            self.buf.copy_within(self.start..self.end, 0);
+            self.end -= self.start;
+            self.start = 0;
+
+            while self.end < min {
+                match self.inner.read(&mut self.buf[self.end..]) {
+                    Ok(0) => break,
+                    Ok(n) => self.end += n,
+                    Err(e) if e.kind() == bitcoin::io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(&self.buf[self.start..self.end])
+    }
+
+    /// Mark `n` bytes as read, discarding any beyond the buffered data
+    pub(crate) fn skip(&mut self, n: usize) -> bitcoin::io::Result<()> {
+        let mut remaining = n;
+        loop {
+            let available = self.end - self.start;
+            if remaining <= available {
+                self.consume(remaining);
+                return Ok(());
+            }
+            self.consume(available);
+            remaining -= available;
+
+            self.start = 0;
+            self.end = 0;
+            let to_read = remaining.min(BUFFER_SIZE);
+            if self.fill(to_read)?.len() < to_read {
+                return Err(bitcoin::io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+    }
+}
+
+impl<R> Source<R>
+where
+    R: Seek,
+{
+    /// Move to an absolute byte offset from the start of the dump
+    pub(crate) fn seek_to(&mut self, position: u64) -> std::io::Result<()> {
+        // Stay within the buffer when possible
+        let buffer_start = self.position - self.start as u64;
+        let buffer_end = self.position + (self.end - self.start) as u64;
+        if (buffer_start..=buffer_end).contains(&position) {
+            self.start = (position - buffer_start) as usize;
+        } else {
+            self.inner.seek(SeekFrom::Start(position))?;
+            self.start = 0;
+            self.end = 0;
+        }
+        self.position = position;
+
+        Ok(())
+    }
+
+    /// Total length of the dump in bytes
+    pub(crate) fn len(&mut self) -> std::io::Result<u64> {
+        let inner_position = self.position + (self.end - self.start) as u64;
+        let len = self.inner.seek(SeekFrom::End(0))?;
+        self.inner.seek(SeekFrom::Start(inner_position))?;
+        Ok(len)
+    }
+}
@@ -0,0 +1,317 @@
+//! The cost of spending coins and dust under Bitcoin Core's relay rules
+//!
+//! Input sizes are estimates for the common way of spending each script type:
+//! compressed keys, 72-byte ECDSA signatures and key path Schnorr signatures
+//! with the default sighash. The segwit marker and flag, shared by all inputs
+//! of a transaction, are not counted.
+
+use bitcoin::io::Read;
+use bitcoin::{opcodes, FeeRate, SignedAmount, Weight};
+
+use crate::{Amount, Dump, Error, TxOut};
+
+/// Bitcoin Core's default `-dustrelayfee` of 3 sat/vB
+pub const DUST_RELAY_FEE: FeeRate = FeeRate::from_sat_per_vb_u32(3);
+
+/// Out point, sequence number and script length of an input without a
+/// script signature
+const INPUT_BASE_SIZE: u64 = 32 + 4 + 4 + 1;
+
+/// A push of a DER signature with sighash flag
+const ECDSA_SIG_PUSH: u64 = 1 + 72;
+
+/// A push of a compressed public key
+const PUBKEY_PUSH: u64 = 1 + 33;
+
+/// Witness of a P2WPKH input: item count, signature and public key
+const P2WPKH_WITNESS: u64 = 1 + ECDSA_SIG_PUSH + PUBKEY_PUSH;
+
+/// The pay-to-anchor script
+const P2A: [u8; 4] = [0x51, 0x02, 0x4e, 0x73];
+
+impl TxOut {
+    /// Estimated weight of an input spending this output
+    ///
+    /// P2SH outputs are assumed to wrap P2WPKH. Returns `None` for
+    /// unspendable outputs and scripts whose spending input cannot be
+    /// guessed, such as P2WSH and non-standard scripts.
+    pub fn input_weight(&self) -> Option<Weight> {
+        let script = self.script_pubkey.as_script();
+        // Script signature and witness sizes
+        let (script_sig, witness) = if script.is_p2pkh() {
+            (ECDSA_SIG_PUSH + PUBKEY_PUSH, 0)
+        } else if script.is_p2pk() {
+            (ECDSA_SIG_PUSH, 0)
+        } else if script.is_p2wpkh() {
+            (0, P2WPKH_WITNESS)
+        } else if script.is_p2sh() {
+            // A push of the P2WPKH redeem script
+            (1 + 22, P2WPKH_WITNESS)
+        } else if script.is_p2tr() {
+            (0, 1 + 1 + 64)
+        } else if script.as_bytes() == P2A {
+            (0, 1)
+        } else if script.is_multisig() {
+            // OP_0 for the off-by-one bug, then the signatures
+            let required = script.as_bytes()[0] - opcodes::all::OP_PUSHNUM_1.to_u8() + 1;
+            (1 + ECDSA_SIG_PUSH * u64::from(required), 0)
+        } else {
+            return None;
+        };
+
+        // The script length is a compact size; the base already has one byte
+        let length_size = if script_sig < 0xfd { 0 } else { 2 };
+        let base = INPUT_BASE_SIZE + length_size + script_sig;
+        Some(Weight::from_wu(base * 4 + witness))
+    }
+
+    /// Estimated virtual size of an input spending this output
+    pub fn input_vsize(&self) -> Option<u64> {
+        self.input_weight().map(Weight::to_vbytes_ceil)
+    }
+
+    /// Estimated fee to spend this output at `fee_rate`
+    pub fn spend_fee(&self, fee_rate: FeeRate) -> Option<Amount> {
+        let fee = fee_rate.fee_wu(self.input_weight()?)?;
+        Some(fee.into())
+    }
+
+    /// The amount minus the fee to spend this output at `fee_rate`
+    ///
+    /// Outputs with a negative or zero effective value cost more to spend than
+    /// they are worth. Returns `None` when the spending input cannot be
+    /// estimated or the amount does not fit a `SignedAmount`.
+    pub fn effective_value(&self, fee_rate: FeeRate) -> Option<SignedAmount> {
+        let amount = SignedAmount::from_sat(i64::try_from(self.amount.to_sat()).ok()?);
+        let fee = SignedAmount::from_sat(i64::try_from(self.spend_fee(fee_rate)?.to_sat()).ok()?);
+        amount.checked_sub(fee)
+    }
+
+    /// Whether this output is dust under Bitcoin Core's relay policy with
+    /// `-dustrelayfee` at `dust_relay_fee`
+    ///
+    /// Unspendable outputs are never dust.
+    pub fn is_dust(&self, dust_relay_fee: FeeRate) -> bool {
+        let threshold = self.script_pubkey.minimal_non_dust_custom(dust_relay_fee);
+        self.amount.to_sat() < threshold.to_sat()
+    }
+}
+
+/// Count and value of a set of coins, such as a category of [`SpendCost`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Tally {
+    /// Number of coins
+    pub utxo_count: u64,
+    /// Combined value of the coins, saturating at `u64::MAX` satoshis
+    pub amount: Amount,
+}
+
+impl Tally {
+    /// Count one coin, saturating the amount rather than overflowing
+    ///
+    /// Totals from a dump stay within `MAX_MONEY`, but coins added directly,
+    /// as with [`SpendCost::add`], may exceed it.
+    pub fn add(&mut self, amount: Amount) {
+        self.utxo_count += 1;
+        self.amount = Amount::new(self.amount.to_sat().saturating_add(amount.to_sat()));
+    }
+}
+
+/// Spending costs of a set of coins at one fee rate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpendCost {
+    /// Fee rate for spending
+    pub fee_rate: FeeRate,
+    /// Fee rate for the dust threshold
+    pub dust_relay_fee: FeeRate,
+    /// Every coin added
+    pub total: Tally,
+    /// Coins whose spending input cannot be estimated
+    pub unknown: Tally,
+    /// Coins worth no more than the fee to spend them
+    pub uneconomical: Tally,
+    /// Coins below the dust threshold
+    pub dust: Tally,
+    /// Combined virtual size of inputs spending the estimated coins
+    pub input_vsize: u64,
+    /// Fee to spend all estimated coins, as when consolidating them
+    pub spend_fee: Amount,
+}
+
+impl SpendCost {
+    /// No coins yet
+    pub fn new(fee_rate: FeeRate, dust_relay_fee: FeeRate) -> Self {
+        Self {
+            fee_rate,
+            dust_relay_fee,
+            total: Tally::default(),
+            unknown: Tally::default(),
+            uneconomical: Tally::default(),
+            dust: Tally::default(),
+            input_vsize: 0,
+            spend_fee: Amount::ZERO,
+        }
+    }
+
+    /// Account for one coin
+    pub fn add(&mut self, tx_out: &TxOut) {
+        self.total.add(tx_out.amount);
+        if tx_out.is_dust(self.dust_relay_fee) {
+            self.dust.add(tx_out.amount);
+        }
+
+        let Some(weight) = tx_out.input_weight() else {
+            self.unknown.add(tx_out.amount);
+            return;
+        };
+        let fee = self
+            .fee_rate
+            .fee_wu(weight)
+            .map_or(Amount::MAX_MONEY, Amount::from);
+        if tx_out.amount <= fee {
+            self.uneconomical.add(tx_out.amount);
+        }
+        self.input_vsize = self.input_vsize.saturating_add(weight.to_vbytes_ceil());
+        self.spend_fee = Amount::new(self.spend_fee.to_sat().saturating_add(fee.to_sat()));
+    }
+}
+
+/// Tally the spending costs of every coin in `dump`
+pub fn spend_cost<R>(
+    mut dump: Dump<R>,
+    fee_rate: FeeRate,
+    dust_relay_fee: FeeRate,
+) -> Result<SpendCost, Error>
+where
+    R: Read,
+{
+    let mut cost = SpendCost::new(fee_rate, dust_relay_fee);
+    let mut tx_out = TxOut::default();
+    while dump.read_into(&mut tx_out)? {
+        cost.add(&tx_out);
+    }
+
+    Ok(cost)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use bitcoin::hashes::Hash;
+    use bitcoin::key::{TweakedPublicKey, XOnlyPublicKey};
+    use bitcoin::{PubkeyHash, ScriptBuf, ScriptHash, WPubkeyHash};
+
+    use super::*;
+    use crate::test::DUMP_28_0;
+    use crate::ComputeAddresses;
+
+    fn tx_out(script_pubkey: ScriptBuf, amount: u64) -> TxOut {
+        TxOut {
+            script_pubkey,
+            amount: Amount::new(amount),
+            ..TxOut::default()
+        }
+    }
+
+    #[test]
+    fn input_sizes() {
+        let key = XOnlyPublicKey::from_slice(&[
+            0x79, 0xbe, 0x66, 0x7e, 0xf9, 0xdc, 0xbb, 0xac, 0x55, 0xa0, 0x62, 0x95, 0xce, 0x87,
+            0x0b, 0x07, 0x02, 0x9b, 0xfc, 0xdb, 0x2d, 0xce, 0x28, 0xd9, 0x59, 0xf2, 0x81, 0x5b,
+            0x16, 0xf8, 0x17, 0x98,
+        ])
+        .unwrap();
+        let vsize = |script| tx_out(script, 0).input_vsize();
+
+        assert_eq!(
+            vsize(ScriptBuf::new_p2pkh(&PubkeyHash::all_zeros())),
+            Some(148)
+        );
+        assert_eq!(
+            vsize(ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros())),
+            Some(68)
+        );
+        assert_eq!(
+            vsize(ScriptBuf::new_p2sh(&ScriptHash::all_zeros())),
+            Some(91)
+        );
+        assert_eq!(
+            vsize(ScriptBuf::new_p2tr_tweaked(
+                TweakedPublicKey::dangerous_assume_tweaked(key)
+            )),
+            Some(58)
+        );
+        assert_eq!(vsize(ScriptBuf::from_bytes(P2A.to_vec())), Some(42));
+        assert_eq!(vsize(ScriptBuf::new_op_return([1; 4])), None);
+
+        // 2-of-3 bare multisig
+        let pubkey =
+            bitcoin::PublicKey::from_slice(&[&[0x02][..], &key.serialize()[..]].concat()).unwrap();
+        let multisig = bitcoin::script::Builder::new()
+            .push_opcode(opcodes::all::OP_PUSHNUM_2)
+            .push_key(&pubkey)
+            .push_key(&pubkey)
+            .push_key(&pubkey)
+            .push_opcode(opcodes::all::OP_PUSHNUM_3)
+            .push_opcode(opcodes::all::OP_CHECKMULTISIG)
+            .into_script();
+        assert_eq!(vsize(multisig), Some(41 + 1 + 146));
+    }
+
+    #[test]
+    fn dust_and_effective_value() {
+        let p2pkh = || ScriptBuf::new_p2pkh(&PubkeyHash::all_zeros());
+        let p2wpkh = || ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros());
+
+        assert!(tx_out(p2pkh(), 545).is_dust(DUST_RELAY_FEE));
+        assert!(!tx_out(p2pkh(), 546).is_dust(DUST_RELAY_FEE));
+        assert!(tx_out(p2wpkh(), 293).is_dust(DUST_RELAY_FEE));
+        assert!(!tx_out(p2wpkh(), 294).is_dust(DUST_RELAY_FEE));
+        assert!(!tx_out(ScriptBuf::new_op_return([1; 4]), 0).is_dust(DUST_RELAY_FEE));
+
+        let fee_rate = FeeRate::from_sat_per_vb_u32(10);
+        assert_eq!(
+            tx_out(p2wpkh(), 1000).effective_value(fee_rate),
+            Some(SignedAmount::from_sat(320))
+        );
+        assert_eq!(
+            tx_out(p2wpkh(), 100).effective_value(fee_rate),
+            Some(SignedAmount::from_sat(-580))
+        );
+    }
+
+    #[test]
+    fn tally_dump() {
+        let dump = || Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap();
+        let fee_rate = FeeRate::from_sat_per_vb_u32(10);
+
+        let cost = spend_cost(dump(), fee_rate, DUST_RELAY_FEE).unwrap();
+        assert_eq!(cost.total.utxo_count, 100);
+        assert_eq!(cost.unknown, Tally::default());
+        assert_eq!(cost.uneconomical, Tally::default());
+        assert_eq!(cost.dust, Tally::default());
+
+        let vsize: u64 = dump().map(|tx_out| tx_out.input_vsize().unwrap()).sum();
+        assert_eq!(cost.input_vsize, vsize);
+        assert!(cost.spend_fee.to_sat() > 0);
+    }
+
+    #[test]
+    fn saturate_beyond_max_money() {
+        let mut cost = SpendCost::new(FeeRate::from_sat_per_vb_u32(10), DUST_RELAY_FEE);
+        let coin = tx_out(ScriptBuf::new_op_return([1; 4]), u64::MAX);
+        cost.add(&coin);
+        cost.add(&coin);
+        assert_eq!(cost.total.utxo_count, 2);
+        assert_eq!(cost.total.amount, Amount::new(u64::MAX));
+        assert_eq!(cost.unknown, cost.total);
+
+        let p2wpkh = tx_out(ScriptBuf::new_p2wpkh(&WPubkeyHash::all_zeros()), 1 << 63);
+        assert_eq!(p2wpkh.effective_value(cost.fee_rate), None);
+        cost.input_vsize = u64::MAX;
+        cost.add(&p2wpkh);
+        assert_eq!(cost.input_vsize, u64::MAX);
+        assert_eq!(cost.total.amount, Amount::new(u64::MAX));
+    }
+}
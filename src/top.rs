@@ -0,0 +1,150 @@
+//! The largest coins and richest scripts of a dump
+//!
+//! Both reports keep only `n` candidates in a heap, so memory does not grow
+//! with the size of the dump.
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use bitcoin::io::Read;
+
+use crate::aggregate::Balances;
+use crate::{Balance, Dump, Error, TxOut};
+
+/// The `n` largest entries of `dump` by amount, largest first
+///
+/// Entries of equal amount are ranked by ascending out point.
+pub fn top_tx_outs<R>(mut dump: Dump<R>, n: usize) -> Result<Vec<TxOut>, Error>
+where
+    R: Read,
+{
+    let mut top = Top::new(n);
+    while let Some(tx_out) = dump.try_next()? {
+        let rank = (tx_out.amount, Reverse(tx_out.out_point));
+        top.push(rank, tx_out);
+    }
+
+    Ok(top.into_sorted())
+}
+
+/// The `n` scripts with the largest balances, largest first
+///
+/// Balances of equal amount are ranked by ascending script bytes.
+pub fn top_balances(mut balances: Balances, n: usize) -> Result<Vec<Balance>, Error> {
+    let mut top = Top::new(n);
+    while let Some(balance) = balances.try_next()? {
+        let rank = (balance.total_amount, Reverse(balance.script_pubkey.clone()));
+        top.push(rank, balance);
+    }
+
+    Ok(top.into_sorted())
+}
+
+/// The `n` items of greatest rank seen so far
+struct Top<K, T> {
+    /// Min-heap of the candidates, the weakest on top
+    heap: BinaryHeap<Reverse<Ranked<K, T>>>,
+    n: usize,
+}
+
+impl<K, T> Top<K, T>
+where
+    K: Ord,
+{
+    fn new(n: usize) -> Self {
+        Self {
+            heap: BinaryHeap::with_capacity(n.saturating_add(1).min(1 << 16)),
+            n,
+        }
+    }
+
+    fn push(&mut self, rank: K, item: T) {
+        if self.n == 0 {
+            return;
+        }
+        if self.heap.len() == self.n {
+            match self.heap.peek() {
+                Some(Reverse(weakest)) if weakest.0 >= rank => return,
+                _ => {
+                    self.heap.pop();
+                }
+            }
+        }
+        self.heap.push(Reverse(Ranked(rank, item)));
+    }
+
+    /// The candidates, greatest rank first
+    fn into_sorted(self) -> Vec<T> {
+        // Ascending order of `Reverse` is descending rank
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(Ranked(_, item))| item)
+            .collect()
+    }
+}
+
+/// An item ordered by its rank alone
+struct Ranked<K, T>(K, T);
+
+impl<K: Ord, T> PartialEq for Ranked<K, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<K: Ord, T> Eq for Ranked<K, T> {}
+
+impl<K: Ord, T> PartialOrd for Ranked<K, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, T> Ord for Ranked<K, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test::DUMP_28_0;
+    use crate::{Aggregator, ComputeAddresses};
+
+    fn dump() -> Dump<Cursor<&'static [u8]>> {
+        Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap()
+    }
+
+    #[test]
+    fn largest_coins() {
+        let mut expected: Vec<_> = dump().collect();
+        expected.sort_by(|a, b| {
+            b.amount
+                .cmp(&a.amount)
+                .then_with(|| a.out_point.cmp(&b.out_point))
+        });
+
+        for n in [0, 1, 10, 100, 1000] {
+            let top = top_tx_outs(dump(), n).unwrap();
+            assert_eq!(top, expected[..n.min(100)]);
+        }
+    }
+
+    #[test]
+    fn richest_scripts() {
+        let balances = || Aggregator::new().aggregate(dump()).unwrap();
+        let mut expected: Vec<_> = balances().collect();
+        expected.sort_by(|a, b| {
+            b.total_amount
+                .cmp(&a.total_amount)
+                .then_with(|| a.script_pubkey.cmp(&b.script_pubkey))
+        });
+
+        let top = top_balances(balances(), 5).unwrap();
+        assert_eq!(top, expected[..5]);
+    }
+}
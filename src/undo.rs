@@ -0,0 +1,288 @@
+//! Bitcoin Core's block undo data from `rev*.dat` files
+//!
+//! Connecting a block stores the coins it spends, so the block can later be
+//! disconnected with [`crate::Update::disconnect`]. Each spent coin uses the
+//! [`Coin`] serialization with an extra zero byte after the code for coins
+//! with a height, left over from an older format.
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use bitcoin::consensus::{Decodable, Encodable};
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoin::io::{Read, Write};
+use bitcoin::{Block, BlockHash};
+
+use crate::blocks::Records;
+use crate::coin::Code;
+use crate::{Amount, Coin, CompactSize, Error, Script, VarInt};
+
+/// Largest undo record accepted, Bitcoin Core's limit on serialized objects
+const MAX_UNDO_SIZE: u32 = 0x0200_0000;
+
+/// The coins spent by a block, needed to disconnect it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockUndo {
+    /// Spent coins of each transaction after the coinbase, in input order
+    pub txs: Vec<Vec<Coin>>,
+}
+
+impl BlockUndo {
+    /// The checksum stored after the undo data of the block whose parent is
+    /// `prev_blockhash`
+    pub fn checksum(&self, prev_blockhash: BlockHash) -> sha256d::Hash {
+        let mut engine = sha256d::Hash::engine();
+        engine.input(prev_blockhash.as_byte_array());
+        self.consensus_encode(&mut engine)
+            .expect("hash engines do not fail");
+        sha256d::Hash::from_engine(engine)
+    }
+
+    /// Whether there is a coin for every input of the block
+    pub fn fits(&self, block: &Block) -> bool {
+        let txs = block.txdata.get(1..).unwrap_or_default();
+        self.txs.len() == txs.len()
+            && self
+                .txs
+                .iter()
+                .zip(txs)
+                .all(|(coins, tx)| coins.len() == tx.input.len())
+    }
+}
+
+impl Encodable for BlockUndo {
+    fn consensus_encode<W: Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        let mut len = CompactSize::from(self.txs.len() as u64).consensus_encode(writer)?;
+        for coins in &self.txs {
+            len += CompactSize::from(coins.len() as u64).consensus_encode(writer)?;
+            for coin in coins {
+                len += encode_coin(coin, writer)?;
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+impl Decodable for BlockUndo {
+    fn consensus_decode<R: Read + ?Sized>(
+        reader: &mut R,
+    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+        let tx_count = u64::from(CompactSize::consensus_decode(reader)?);
+        let mut txs = Vec::new();
+        for _ in 0..tx_count {
+            let coin_count = u64::from(CompactSize::consensus_decode(reader)?);
+            let mut coins = Vec::new();
+            for _ in 0..coin_count {
+                coins.push(decode_coin(reader)?);
+            }
+            txs.push(coins);
+        }
+
+        Ok(Self { txs })
+    }
+}
+
+/// Undo data read from a `rev*.dat` file with its stored checksum
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UndoRecord {
+    /// The coins spent by the block
+    pub undo: BlockUndo,
+    /// Checksum committing to the parent of the block and the undo data
+    pub checksum: sha256d::Hash,
+}
+
+impl UndoRecord {
+    /// Whether this is the undo data of `block`
+    ///
+    /// Undo files do not name their blocks, so the checksum identifies them.
+    pub fn matches(&self, block: &Block) -> bool {
+        self.undo.fits(block) && self.undo.checksum(block.header.prev_blockhash) == self.checksum
+    }
+}
+
+/// Iterator over the undo records stored in a `rev*.dat` file
+///
+/// Records are framed like blocks in `blk*.dat` files, followed by their
+/// checksum.
+pub struct UndoFile<R> {
+    /// First error encountered while iterating
+    error: Option<Error>,
+    /// The framed records of the file
+    records: Records<R>,
+}
+
+impl<R> UndoFile<R>
+where
+    R: Read,
+{
+    /// Read undo records of `network` from the start of an undo file
+    pub fn new(reader: R, network: bitcoin::Network) -> Self {
+        Self {
+            error: None,
+            records: Records::new(reader, network),
+        }
+    }
+
+    /// Undo the obfuscation Bitcoin Core 28.0 and later apply to undo files
+    pub fn with_xor_key(mut self, xor_key: [u8; 8]) -> Self {
+        self.records.xor_key = xor_key;
+        self
+    }
+
+    /// The error that stopped iteration, if any
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Read the next record, distinguishing the end of the file from errors
+    pub fn try_next(&mut self) -> Result<Option<UndoRecord>, Error> {
+        let Some(payload) = self.records.next_record(MAX_UNDO_SIZE)? else {
+            return Ok(None);
+        };
+        let undo = bitcoin::consensus::deserialize(&payload)?;
+
+        let mut checksum = [0; 32];
+        self.records.read_exact(&mut checksum)?;
+
+        Ok(Some(UndoRecord {
+            undo,
+            checksum: sha256d::Hash::from_byte_array(checksum),
+        }))
+    }
+}
+
+impl UndoFile<BufReader<File>> {
+    /// Open an undo file, using the `xor.dat` key next to it when present
+    pub fn open(path: impl AsRef<Path>, network: bitcoin::Network) -> Result<Self, Error> {
+        Ok(Self {
+            error: None,
+            records: Records::open(path.as_ref(), network)?,
+        })
+    }
+}
+
+impl<R> Iterator for UndoFile<R>
+where
+    R: Read,
+{
+    type Item = UndoRecord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.try_next() {
+            Ok(item) => item,
+            Err(e) => {
+                self.records.finished = true;
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// Encode a spent coin in the undo format
+fn encode_coin<W: Write + ?Sized>(
+    coin: &Coin,
+    writer: &mut W,
+) -> Result<usize, bitcoin::io::Error> {
+    let code = Code {
+        height: coin.height,
+        is_coinbase: coin.is_coinbase,
+    };
+    let script = Script::from_parts(coin.txout.script_pubkey.clone(), coin.script_anomaly);
+
+    let mut len = code.consensus_encode(writer)?;
+    if coin.height > 0 {
+        len += VarInt::from(0_u8).consensus_encode(writer)?;
+    }
+    len += Amount::from(coin.txout.value).consensus_encode(writer)?;
+    len += script.consensus_encode(writer)?;
+
+    Ok(len)
+}
+
+/// Decode a spent coin in the undo format
+fn decode_coin<R: Read + ?Sized>(
+    reader: &mut R,
+) -> Result<Coin, bitcoin::consensus::encode::Error> {
+    let code = Code::consensus_decode(reader)?;
+    if code.height > 0 {
+        // Transaction version of old undo records, now always zero
+        VarInt::consensus_decode(reader)?;
+    }
+    let amount = Amount::consensus_decode(reader)?;
+    let script = Script::consensus_decode(reader)?;
+    let script_anomaly = script.anomaly();
+
+    Ok(Coin {
+        height: code.height,
+        is_coinbase: code.is_coinbase,
+        txout: bitcoin::TxOut {
+            value: amount.into(),
+            script_pubkey: script.into_inner(),
+        },
+        script_anomaly,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use bitcoin::consensus::serialize;
+    use bitcoin::ScriptBuf;
+
+    use super::*;
+
+    fn undo() -> BlockUndo {
+        let coin = |height, value| {
+            Coin::new(
+                height,
+                height % 2 == 0,
+                bitcoin::TxOut {
+                    value: bitcoin::Amount::from_sat(value),
+                    script_pubkey: ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::all_zeros()),
+                },
+            )
+        };
+        BlockUndo {
+            txs: vec![vec![coin(100, 5_000)], vec![coin(7, 1), coin(300_000, 0)]],
+        }
+    }
+
+    #[test]
+    fn undo_format() {
+        let undo = undo();
+        let bytes = serialize(&undo);
+
+        // Two transactions, one coin, code 201, the zero byte, amount 5000 and
+        // the size of a P2WPKH script
+        assert_eq!(bytes[..7], [2, 1, 0x80, 0x49, 0, 0x2c, 22 + 6]);
+        assert_eq!(
+            bitcoin::consensus::deserialize::<BlockUndo>(&bytes).unwrap(),
+            undo
+        );
+    }
+
+    #[test]
+    fn read_undo_file() {
+        let undo = undo();
+        let prev_blockhash = BlockHash::from_byte_array([3; 32]);
+        let payload = serialize(&undo);
+
+        let mut file = Vec::new();
+        file.extend_from_slice(&bitcoin::Network::Signet.magic().to_bytes());
+        file.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        file.extend_from_slice(&payload);
+        file.extend_from_slice(undo.checksum(prev_blockhash).as_byte_array());
+
+        let mut records = UndoFile::new(file.as_slice(), bitcoin::Network::Signet);
+        let record = records.next().unwrap();
+        assert!(records.next().is_none());
+        assert!(records.error().is_none());
+        assert_eq!(record.undo, undo);
+        assert_eq!(record.checksum, undo.checksum(prev_blockhash));
+    }
+}
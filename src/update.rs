@@ -0,0 +1,733 @@
+//! Rolling a snapshot forward by applying blocks, or back by disconnecting
+//! them
+//!
+//! [`Update`] collects the effect of a run of blocks on top of a snapshot:
+//! the snapshot coins they spend and the new coins still unspent at the new
+//! tip. Disconnecting a block with its [`BlockUndo`] data reverses that,
+//! removing the block's outputs and restoring the coins it spent. Streaming
+//! the snapshot through [`Update::changes`] or [`Update::write_snapshot`]
+//! then checks that every spent coin exists. Only the blocks' effects are
+//! held in memory, never the whole UTXO set.
+//!
+//! Scripts, amounts and proof of work are not validated; blocks are expected
+//! to come from a node that already accepted them.
+
+use std::collections::btree_map;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::{Seek, Write};
+
+use bitcoin::io::Read;
+use bitcoin::{Block, BlockHash, OutPoint};
+
+use crate::script::MAX_SCRIPT_SIZE;
+use crate::snapshot::SnapshotWriter;
+use crate::undo::{BlockUndo, UndoRecord};
+use crate::{extend_out_point_key, Coin, Dump, Error, TxOut};
+
+/// The effect of a run of blocks on a snapshot
+#[derive(Debug, Clone)]
+pub struct Update {
+    /// Hash of the snapshot base block
+    base_hash: BlockHash,
+    /// Coins unspent at the tip but not in the snapshot, by key
+    created: BTreeMap<Vec<u8>, (OutPoint, Coin)>,
+    /// Height of the tip
+    height: u32,
+    /// Snapshot coins no longer unspent at the tip, with the block that
+    /// spent or removed them
+    spent: HashMap<OutPoint, BlockHash>,
+    /// Hash of the block the update has reached
+    tip: BlockHash,
+}
+
+/// A difference between the snapshot and the UTXO set at the new tip
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// A snapshot coin that is spent at the new tip
+    Spent(TxOut),
+    /// A coin that is unspent at the new tip but not in the snapshot
+    Created(TxOut),
+}
+
+impl Update {
+    /// Start from a snapshot taken at `base_hash`, at height `base_height`
+    pub fn new(base_hash: BlockHash, base_height: u32) -> Self {
+        Self {
+            base_hash,
+            created: BTreeMap::new(),
+            height: base_height,
+            spent: HashMap::new(),
+            tip: base_hash,
+        }
+    }
+
+    /// Hash of the block the update has reached
+    pub fn tip(&self) -> BlockHash {
+        self.tip
+    }
+
+    /// Height of the block the update has reached
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Apply the next block, whose parent must be the current tip
+    ///
+    /// After an error the update is partially applied and should be
+    /// discarded.
+    pub fn apply(&mut self, block: &Block) -> Result<(), Error> {
+        let block_hash = block.block_hash();
+        if block.header.prev_blockhash != self.tip {
+            return Err(Error::Disconnected {
+                block: block_hash,
+                tip: self.tip,
+            });
+        }
+        if !block.check_merkle_root() {
+            return Err(Error::BadMerkleRoot(block_hash));
+        }
+
+        let height = self.height + 1;
+        let mut key = Vec::new();
+        for tx in &block.txdata {
+            let is_coinbase = tx.is_coinbase();
+            if !is_coinbase {
+                for input in &tx.input {
+                    key.clear();
+                    extend_out_point_key(&mut key, &input.previous_output);
+                    if self.created.remove(&key).is_some() {
+                        continue;
+                    }
+                    if self
+                        .spent
+                        .insert(input.previous_output, block_hash)
+                        .is_some()
+                    {
+                        return Err(Error::MissingInput {
+                            out_point: input.previous_output,
+                            block: block_hash,
+                        });
+                    }
+                }
+            }
+
+            let txid = tx.compute_txid();
+            for (vout, output) in (0..).zip(&tx.output) {
+                if is_unspendable(output) {
+                    continue;
+                }
+
+                let out_point = OutPoint::new(txid, vout);
+                let mut key = Vec::new();
+                extend_out_point_key(&mut key, &out_point);
+                let coin = Coin::new(height, is_coinbase, output.clone());
+                self.created.insert(key, (out_point, coin));
+            }
+        }
+
+        self.height = height;
+        self.tip = block_hash;
+
+        Ok(())
+    }
+
+    /// Apply the longest chain of `blocks` extending the current tip
+    ///
+    /// The blocks may come in any order, as in `blk*.dat` files, and may
+    /// include stale or unrelated blocks. Between equally long branches the
+    /// one seen first wins. Every block is held in memory until applied.
+    /// Returns the number of blocks applied.
+    pub fn apply_chain<I>(&mut self, blocks: I) -> Result<usize, Error>
+    where
+        I: IntoIterator<Item = Block>,
+    {
+        let mut by_hash = HashMap::new();
+        let mut children: HashMap<BlockHash, Vec<BlockHash>> = HashMap::new();
+        for block in blocks {
+            let block_hash = block.block_hash();
+            let prev_blockhash = block.header.prev_blockhash;
+            if by_hash.insert(block_hash, block).is_none() {
+                children.entry(prev_blockhash).or_default().push(block_hash);
+            }
+        }
+
+        // Breadth-first from the tip, so the first block found at the greatest
+        // depth ends the chain to apply
+        let mut parents = HashMap::new();
+        let mut end = (0, self.tip);
+        let mut queue = VecDeque::from([(self.tip, 0)]);
+        while let Some((block_hash, depth)) = queue.pop_front() {
+            if depth > end.0 {
+                end = (depth, block_hash);
+            }
+            for &child in children.get(&block_hash).into_iter().flatten() {
+                parents.insert(child, block_hash);
+                queue.push_back((child, depth + 1));
+            }
+        }
+
+        let mut chain = Vec::with_capacity(end.0);
+        let mut block_hash = end.1;
+        while block_hash != self.tip {
+            chain.push(block_hash);
+            block_hash = parents[&block_hash];
+        }
+
+        for block_hash in chain.iter().rev() {
+            self.apply(&by_hash[block_hash])?;
+        }
+
+        Ok(chain.len())
+    }
+
+    /// Disconnect the tip block, restoring the coins it spent from `undo`
+    ///
+    /// Fails with [`Error::DisconnectGenesis`] at height 0. After an error
+    /// the update is partially applied and should be discarded.
+    pub fn disconnect(&mut self, block: &Block, undo: &BlockUndo) -> Result<(), Error> {
+        let block_hash = block.block_hash();
+        if block_hash != self.tip {
+            return Err(Error::NotTip {
+                block: block_hash,
+                tip: self.tip,
+            });
+        }
+        if self.height == 0 {
+            return Err(Error::DisconnectGenesis(block_hash));
+        }
+        if !block.check_merkle_root() {
+            return Err(Error::BadMerkleRoot(block_hash));
+        }
+        if !undo.fits(block) {
+            return Err(Error::UndoMismatch(block_hash));
+        }
+
+        let mut key = Vec::new();
+        for (index, tx) in block.txdata.iter().enumerate().rev() {
+            let txid = tx.compute_txid();
+            for (vout, output) in (0..).zip(&tx.output) {
+                if is_unspendable(output) {
+                    continue;
+                }
+
+                let out_point = OutPoint::new(txid, vout);
+                key.clear();
+                extend_out_point_key(&mut key, &out_point);
+                if self.created.remove(&key).is_some() {
+                    continue;
+                }
+                if self.spent.insert(out_point, block_hash).is_some() {
+                    return Err(Error::MissingInput {
+                        out_point,
+                        block: block_hash,
+                    });
+                }
+            }
+
+            let Some(coins) = index.checked_sub(1).map(|index| &undo.txs[index]) else {
+                continue;
+            };
+            for (input, coin) in tx.input.iter().zip(coins).rev() {
+                // Records from before Bitcoin Core 0.15 may omit the height
+                if coin.height == 0 {
+                    return Err(Error::UndoMismatch(block_hash));
+                }
+
+                let out_point = input.previous_output;
+                if self.spent.remove(&out_point).is_some() {
+                    continue;
+                }
+                let mut key = Vec::new();
+                extend_out_point_key(&mut key, &out_point);
+                if self
+                    .created
+                    .insert(key, (out_point, coin.clone()))
+                    .is_some()
+                {
+                    return Err(Error::UndoMismatch(block_hash));
+                }
+            }
+        }
+
+        self.height -= 1;
+        self.tip = block.header.prev_blockhash;
+
+        Ok(())
+    }
+
+    /// Disconnect blocks until the tip is at `height`
+    ///
+    /// The blocks and undo records may come in any order, as in `blk*.dat`
+    /// and `rev*.dat` files, and may include unrelated ones; each undo record
+    /// is matched to its block by checksum. Every block is held in memory.
+    /// Returns the number of blocks disconnected.
+    pub fn disconnect_to<B, U>(&mut self, height: u32, blocks: B, undos: U) -> Result<usize, Error>
+    where
+        B: IntoIterator<Item = Block>,
+        U: IntoIterator<Item = UndoRecord>,
+    {
+        let mut blocks: HashMap<_, _> = blocks
+            .into_iter()
+            .map(|block| (block.block_hash(), block))
+            .collect();
+        let mut undos: Vec<_> = undos.into_iter().collect();
+
+        let mut disconnected = 0;
+        while self.height > height {
+            let block = blocks
+                .remove(&self.tip)
+                .ok_or(Error::MissingBlock(self.tip))?;
+            let index = undos
+                .iter()
+                .position(|record| record.matches(&block))
+                .ok_or(Error::MissingUndo(self.tip))?;
+            let record = undos.swap_remove(index);
+
+            self.disconnect(&block, &record.undo)?;
+            disconnected += 1;
+        }
+
+        Ok(disconnected)
+    }
+
+    /// Compare the update with the snapshot it started from
+    ///
+    /// The changes come in key order, as long as the dump is in Bitcoin Core's
+    /// order. A spent coin missing from the dump ends iteration with
+    /// [`Error::MissingInput`].
+    pub fn changes<R>(self, dump: Dump<R>) -> Result<Changes<R>, Error>
+    where
+        R: Read,
+    {
+        Ok(Changes {
+            error: None,
+            merge: Merge::new(self, dump)?,
+        })
+    }
+
+    /// Write the UTXO set at the new tip as a snapshot of `network`
+    ///
+    /// Returns the number of coins written.
+    pub fn write_snapshot<R, W>(
+        self,
+        dump: Dump<R>,
+        network: bitcoin::Network,
+        writer: W,
+    ) -> Result<u64, Error>
+    where
+        R: Read,
+        W: Write + Seek,
+    {
+        if let Some(detected) = dump.network.filter(|&detected| detected != network) {
+            return Err(Error::NetworkMismatch {
+                detected,
+                specified: network,
+            });
+        }
+
+        let mut writer = SnapshotWriter::new(writer, network, self.tip)?;
+        let mut merge = Merge::new(self, dump)?;
+        while let Some(entry) = merge.next_entry()? {
+            match entry {
+                Entry::Kept(tx_out) | Entry::Created(tx_out) => writer.add(tx_out)?,
+                Entry::Spent(_) => {}
+            }
+        }
+
+        writer.finish()
+    }
+}
+
+/// Whether Bitcoin Core leaves an output out of the UTXO set
+fn is_unspendable(output: &bitcoin::TxOut) -> bool {
+    let script = &output.script_pubkey;
+    script.is_op_return() || script.len() > MAX_SCRIPT_SIZE
+}
+
+/// Iterator over the [`Change`]s of an [`Update`]
+pub struct Changes<R>
+where
+    R: Read,
+{
+    /// First error encountered while iterating
+    error: Option<Error>,
+    /// The snapshot merged with the update
+    merge: Merge<R>,
+}
+
+impl<R> Changes<R>
+where
+    R: Read,
+{
+    /// The error that stopped iteration, if any
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Read the next change, distinguishing the end from errors
+    pub fn try_next(&mut self) -> Result<Option<Change>, Error> {
+        while let Some(entry) = self.merge.next_entry()? {
+            match entry {
+                Entry::Kept(_) => {}
+                Entry::Spent(tx_out) => return Ok(Some(Change::Spent(tx_out))),
+                Entry::Created(tx_out) => return Ok(Some(Change::Created(tx_out))),
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+impl<R> Iterator for Changes<R>
+where
+    R: Read,
+{
+    type Item = Change;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.try_next() {
+            Ok(item) => item,
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// An entry of the snapshot merged with an update
+enum Entry {
+    /// A snapshot coin still unspent
+    Kept(TxOut),
+    /// A snapshot coin spent by the update
+    Spent(TxOut),
+    /// A coin created by the update
+    Created(TxOut),
+}
+
+/// Merge of the snapshot entries with the coins created by an update
+struct Merge<R>
+where
+    R: Read,
+{
+    /// Created coins not yet merged
+    created: btree_map::IntoIter<Vec<u8>, (OutPoint, Coin)>,
+    /// The snapshot
+    dump: Dump<R>,
+    /// Set once the snapshot is exhausted
+    dump_finished: bool,
+    /// Next created coin with its key
+    next_created: Option<(Vec<u8>, OutPoint, Coin)>,
+    /// Next snapshot entry with its key
+    next_dump: Option<(Vec<u8>, TxOut)>,
+    /// Spent snapshot coins not yet found
+    spent: HashMap<OutPoint, BlockHash>,
+}
+
+impl<R> Merge<R>
+where
+    R: Read,
+{
+    fn new(update: Update, dump: Dump<R>) -> Result<Self, Error> {
+        if dump.block_hash != update.base_hash {
+            return Err(Error::BaseMismatch {
+                expected: update.base_hash,
+                found: dump.block_hash,
+            });
+        }
+
+        Ok(Self {
+            created: update.created.into_iter(),
+            dump,
+            dump_finished: false,
+            next_created: None,
+            next_dump: None,
+            spent: update.spent,
+        })
+    }
+
+    fn next_entry(&mut self) -> Result<Option<Entry>, Error> {
+        if self.next_dump.is_none() && !self.dump_finished {
+            match self.dump.try_next()? {
+                Some(tx_out) => {
+                    let mut key = Vec::new();
+                    extend_out_point_key(&mut key, &tx_out.out_point);
+                    self.next_dump = Some((key, tx_out));
+                }
+                None => self.dump_finished = true,
+            }
+        }
+        if self.next_created.is_none() {
+            self.next_created = self
+                .created
+                .next()
+                .map(|(key, (out_point, coin))| (key, out_point, coin));
+        }
+
+        let take_dump = match (&self.next_dump, &self.next_created) {
+            (None, None) => {
+                // Report the first missing coin in key order
+                let missing = self.spent.drain().min_by_key(|(out_point, _)| {
+                    let mut key = Vec::new();
+                    extend_out_point_key(&mut key, out_point);
+                    key
+                });
+                return match missing {
+                    Some((out_point, block)) => Err(Error::MissingInput { out_point, block }),
+                    None => Ok(None),
+                };
+            }
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            // A created coin with the key of a snapshot coin replaces it, as
+            // with the duplicate coinbase transactions before BIP 30
+            (Some((dump_key, _)), Some((created_key, ..))) => dump_key <= created_key,
+        };
+
+        if take_dump {
+            let (_, tx_out) = self.next_dump.take().expect("checked above");
+            let replaced = self
+                .next_created
+                .as_ref()
+                .is_some_and(|(_, out_point, _)| *out_point == tx_out.out_point);
+            if self.spent.remove(&tx_out.out_point).is_some() || replaced {
+                return Ok(Some(Entry::Spent(tx_out)));
+            }
+            Ok(Some(Entry::Kept(tx_out)))
+        } else {
+            let (_, out_point, coin) = self.next_created.take().expect("checked above");
+            let tx_out = coin.into_tx_out(out_point, self.dump.address_network);
+            Ok(Some(Entry::Created(tx_out)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use bitcoin::block::{Header, Version};
+    use bitcoin::hashes::Hash;
+    use bitcoin::{
+        absolute, transaction, Amount, CompactTarget, ScriptBuf, Sequence, Transaction, TxIn,
+        TxMerkleNode, Witness,
+    };
+
+    use super::*;
+    use crate::test::DUMP_28_0;
+    use crate::{ComputeAddresses, Dump};
+
+    fn dump() -> Dump<Cursor<&'static [u8]>> {
+        Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap()
+    }
+
+    fn transaction(inputs: &[OutPoint], values: &[u64]) -> Transaction {
+        let input = if inputs.is_empty() {
+            vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::from_bytes(vec![1, 101]),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }]
+        } else {
+            inputs
+                .iter()
+                .map(|&previous_output| TxIn {
+                    previous_output,
+                    ..TxIn::default()
+                })
+                .collect()
+        };
+        let output = values
+            .iter()
+            .map(|&value| bitcoin::TxOut {
+                value: Amount::from_sat(value),
+                script_pubkey: ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::all_zeros()),
+            })
+            .collect();
+
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input,
+            output,
+        }
+    }
+
+    fn block(prev_blockhash: BlockHash, txdata: Vec<Transaction>) -> Block {
+        let mut block = Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: 0,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: 0,
+            },
+            txdata,
+        };
+        block.header.merkle_root = block.compute_merkle_root().unwrap();
+        block
+    }
+
+    #[test]
+    fn roll_forward() {
+        let tx_outs: Vec<_> = dump().collect();
+        let spent = tx_outs[10].out_point;
+
+        let coinbase = transaction(&[], &[50_0000_0000]);
+        let spend = transaction(&[spent], &[1_000, 2_000]);
+        let respend = transaction(&[OutPoint::new(spend.compute_txid(), 1)], &[1_500]);
+        let block = block(
+            dump().block_hash,
+            vec![coinbase.clone(), spend.clone(), respend.clone()],
+        );
+
+        let mut update = Update::new(dump().block_hash, 100);
+        update.apply(&block).unwrap();
+        assert_eq!(update.height(), 101);
+        assert_eq!(update.tip(), block.block_hash());
+
+        let mut changes = update.clone().changes(dump()).unwrap();
+        let changes: Vec<_> = changes.by_ref().collect();
+        assert_eq!(changes.len(), 4);
+        assert!(changes.contains(&Change::Spent(tx_outs[10].clone())));
+        let created: Vec<_> = changes
+            .iter()
+            .filter_map(|change| match change {
+                Change::Created(tx_out) => {
+                    Some((tx_out.out_point, tx_out.height, tx_out.is_coinbase))
+                }
+                Change::Spent(_) => None,
+            })
+            .collect();
+        assert_eq!(created.len(), 3);
+        assert!(created.contains(&(OutPoint::new(coinbase.compute_txid(), 0), 101, true)));
+        assert!(created.contains(&(OutPoint::new(spend.compute_txid(), 0), 101, false)));
+        assert!(created.contains(&(OutPoint::new(respend.compute_txid(), 0), 101, false)));
+
+        let mut out = Cursor::new(Vec::new());
+        let coins = update
+            .write_snapshot(dump(), bitcoin::Network::Signet, &mut out)
+            .unwrap();
+        assert_eq!(coins, 102);
+
+        let rolled =
+            Dump::from_reader(Cursor::new(out.into_inner()), ComputeAddresses::No).unwrap();
+        assert_eq!(rolled.block_hash, block.block_hash());
+        assert_eq!(rolled.utxo_set_size, 102);
+        let rolled: Vec<_> = rolled.collect();
+        assert_eq!(rolled.len(), 102);
+        assert!(!rolled.iter().any(|tx_out| tx_out.out_point == spent));
+    }
+
+    #[test]
+    fn roll_back() {
+        let tx_outs: Vec<_> = dump().collect();
+        let base = dump().block_hash;
+
+        let spend = transaction(&[tx_outs[10].out_point], &[1_000, 2_000]);
+        let respend = transaction(&[OutPoint::new(spend.compute_txid(), 1)], &[1_500]);
+        let block = block(
+            base,
+            vec![transaction(&[], &[50_0000_0000]), spend.clone(), respend],
+        );
+        let undo = BlockUndo {
+            txs: vec![
+                vec![Coin::from(tx_outs[10].clone())],
+                vec![Coin::new(101, false, spend.output[1].clone())],
+            ],
+        };
+
+        // Snapshot after the block
+        let mut update = Update::new(base, 100);
+        update.apply(&block).unwrap();
+        let mut rolled = Cursor::new(Vec::new());
+        update
+            .write_snapshot(dump(), bitcoin::Network::Signet, &mut rolled)
+            .unwrap();
+        let rolled = rolled.into_inner();
+        let rolled_dump =
+            || Dump::from_reader(Cursor::new(rolled.as_slice()), ComputeAddresses::No).unwrap();
+
+        // Undo data for another block is not used
+        let record = UndoRecord {
+            checksum: undo.checksum(BlockHash::all_zeros()),
+            undo: undo.clone(),
+        };
+        let mut back = Update::new(block.block_hash(), 101);
+        assert!(matches!(
+            back.disconnect_to(100, [block.clone()], [record]),
+            Err(Error::MissingUndo(_))
+        ));
+
+        let record = UndoRecord {
+            checksum: undo.checksum(base),
+            undo,
+        };
+        let mut back = Update::new(block.block_hash(), 101);
+        assert_eq!(
+            back.disconnect_to(100, [block.clone()], [record.clone()])
+                .unwrap(),
+            1
+        );
+        assert_eq!(back.tip(), base);
+        assert_eq!(back.height(), 100);
+
+        let mut out = Cursor::new(Vec::new());
+        back.write_snapshot(rolled_dump(), bitcoin::Network::Signet, &mut out)
+            .unwrap();
+        assert_eq!(out.into_inner(), DUMP_28_0);
+
+        let mut genesis = Update::new(block.block_hash(), 0);
+        assert!(matches!(
+            genesis.disconnect(&block, &record.undo),
+            Err(Error::DisconnectGenesis(_))
+        ));
+
+        // Applying and disconnecting the same block cancels out
+        let mut update = Update::new(base, 100);
+        update.apply(&block).unwrap();
+        update.disconnect(&block, &record.undo).unwrap();
+        assert_eq!(update.tip(), base);
+        assert_eq!(update.changes(dump()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn missing_input() {
+        let missing = OutPoint::new(bitcoin::Txid::all_zeros(), 7);
+        let block = block(
+            dump().block_hash,
+            vec![transaction(&[], &[1]), transaction(&[missing], &[1])],
+        );
+        let mut update = Update::new(dump().block_hash, 100);
+        update.apply(&block).unwrap();
+
+        let mut changes = update.changes(dump()).unwrap();
+        assert_eq!(changes.by_ref().count(), 2);
+        assert!(matches!(
+            changes.error(),
+            Some(Error::MissingInput { out_point, .. }) if *out_point == missing
+        ));
+    }
+
+    #[test]
+    fn apply_chain_picks_longest_branch() {
+        let base = dump().block_hash;
+        let a1 = block(base, vec![transaction(&[], &[1])]);
+        let b1 = block(base, vec![transaction(&[], &[2])]);
+        let b2 = block(b1.block_hash(), vec![transaction(&[], &[3])]);
+        let unrelated = block(BlockHash::all_zeros(), vec![transaction(&[], &[4])]);
+
+        let mut update = Update::new(base, 100);
+        let applied = update.apply_chain([b2.clone(), unrelated, a1, b1]).unwrap();
+        assert_eq!(applied, 2);
+        assert_eq!(update.tip(), b2.block_hash());
+        assert_eq!(update.height(), 102);
+
+        assert!(matches!(update.apply(&b2), Err(Error::Disconnected { .. })));
+    }
+}
@@ -0,0 +1,307 @@
+//! Checking a dump against the result printed by `dumptxoutset`
+
+use std::fmt;
+
+use bitcoin::consensus::Encodable;
+use bitcoin::hashes::{sha256, sha256d, Hash};
+use bitcoin::io::Read;
+use bitcoin::BlockHash;
+
+use crate::{Dump, Error, TxOut};
+
+/// The JSON result of the `dumptxoutset` RPC
+///
+/// Operators usually save it next to the snapshot file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Deserialize))]
+pub struct DumpInfo {
+    /// Number of coins in the snapshot
+    pub coins_written: u64,
+    /// Hash of the snapshot base block
+    pub base_hash: BlockHash,
+    /// Height of the snapshot base block
+    pub base_height: u32,
+    /// Hash of the serialized UTXO set, as reported by `gettxoutsetinfo`
+    pub txoutset_hash: sha256d::Hash,
+    /// Number of transactions in the chain up to the base block, which the
+    /// snapshot cannot confirm
+    pub nchaintx: Option<u64>,
+}
+
+#[cfg(feature = "json")]
+impl DumpInfo {
+    /// Parse the saved RPC result
+    pub fn from_json<R: std::io::Read>(reader: R) -> Result<Self, Error> {
+        Ok(serde_json::from_reader(reader)?)
+    }
+}
+
+/// A disagreement between a dump and its [`DumpInfo`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Mismatch {
+    /// The base block hash in the dump header
+    BaseHash {
+        expected: BlockHash,
+        found: BlockHash,
+    },
+    /// The coin count in the dump header
+    HeaderCount { expected: u64, found: u64 },
+    /// The number of coins actually in the dump
+    CoinCount { expected: u64, found: u64 },
+    /// The hash of the coins in the dump
+    TxoutsetHash {
+        expected: sha256d::Hash,
+        found: sha256d::Hash,
+    },
+    /// The height of the youngest coin, which must be in the base block
+    MaxHeight { expected: u32, found: u32 },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::BaseHash { expected, found } => {
+                write!(f, "base hash {found} in header, expected {expected}")
+            }
+            Mismatch::HeaderCount { expected, found } => {
+                write!(f, "coin count {found} in header, expected {expected}")
+            }
+            Mismatch::CoinCount { expected, found } => {
+                write!(f, "{found} coins in dump, expected {expected}")
+            }
+            Mismatch::TxoutsetHash { expected, found } => {
+                write!(f, "txoutset hash {found}, expected {expected}")
+            }
+            Mismatch::MaxHeight { expected, found } => {
+                write!(f, "youngest coin at height {found}, expected {expected}")
+            }
+        }
+    }
+}
+
+/// The outcome of [`validate`]
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Number of coins read from the dump
+    pub coins: u64,
+    /// Height of the youngest coin
+    pub max_height: u32,
+    /// Hash of the coins read from the dump
+    pub txoutset_hash: sha256d::Hash,
+    /// Every check that failed
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Report {
+    /// Whether every check passed
+    pub fn is_valid(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+/// Read the whole dump and compare it with `info`
+///
+/// A dump that fails to decode is an error rather than a mismatch.
+pub fn validate<R>(mut dump: Dump<R>, info: &DumpInfo) -> Result<Report, Error>
+where
+    R: Read,
+{
+    let mut mismatches = Vec::new();
+    if dump.block_hash != info.base_hash {
+        mismatches.push(Mismatch::BaseHash {
+            expected: info.base_hash,
+            found: dump.block_hash,
+        });
+    }
+    if dump.utxo_set_size != info.coins_written {
+        mismatches.push(Mismatch::HeaderCount {
+            expected: info.coins_written,
+            found: dump.utxo_set_size,
+        });
+    }
+
+    let mut hasher = TxoutsetHasher::default();
+    let mut coins = 0;
+    let mut max_height = 0;
+    while let Some(tx_out) = dump.try_next()? {
+        coins += 1;
+        max_height = max_height.max(tx_out.height);
+        hasher.add(tx_out);
+    }
+    let txoutset_hash = hasher.finish();
+
+    if coins != info.coins_written {
+        mismatches.push(Mismatch::CoinCount {
+            expected: info.coins_written,
+            found: coins,
+        });
+    }
+    if txoutset_hash != info.txoutset_hash {
+        mismatches.push(Mismatch::TxoutsetHash {
+            expected: info.txoutset_hash,
+            found: txoutset_hash,
+        });
+    }
+    if max_height != info.base_height {
+        mismatches.push(Mismatch::MaxHeight {
+            expected: info.base_height,
+            found: max_height,
+        });
+    }
+
+    Ok(Report {
+        coins,
+        max_height,
+        txoutset_hash,
+        mismatches,
+    })
+}
+
+/// Bitcoin Core's `hash_serialized` of the UTXO set
+///
+/// SHA256d over each coin's out point, `height * 2 + coinbase` as a `u32` and
+/// full output, visiting transactions in database order and the outputs of
+/// each in index order.
+#[derive(Default)]
+struct TxoutsetHasher {
+    /// SHA256d shares its engine with SHA256
+    engine: sha256::HashEngine,
+    /// Pending outputs of the current transaction
+    group: Vec<TxOut>,
+}
+
+impl TxoutsetHasher {
+    fn add(&mut self, tx_out: TxOut) {
+        if self
+            .group
+            .first()
+            .is_some_and(|first| first.out_point.txid != tx_out.out_point.txid)
+        {
+            self.flush();
+        }
+        self.group.push(tx_out);
+    }
+
+    fn flush(&mut self) {
+        self.group.sort_by_key(|tx_out| tx_out.out_point.vout);
+        for tx_out in self.group.drain(..) {
+            let code = (tx_out.height << 1) | u32::from(tx_out.is_coinbase);
+            let txout = bitcoin::TxOut {
+                value: tx_out.amount.into(),
+                script_pubkey: tx_out.script_pubkey,
+            };
+
+            let engine = &mut self.engine;
+            tx_out
+                .out_point
+                .consensus_encode(engine)
+                .expect("engines do not fail");
+            code.consensus_encode(engine).expect("engines do not fail");
+            txout.consensus_encode(engine).expect("engines do not fail");
+        }
+    }
+
+    fn finish(mut self) -> sha256d::Hash {
+        self.flush();
+        sha256d::Hash::from_engine(self.engine)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test::{DUMP_27_0, DUMP_28_0};
+    use crate::ComputeAddresses;
+
+    fn dump() -> Dump<Cursor<&'static [u8]>> {
+        Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap()
+    }
+
+    /// What `dumptxoutset` would report for the fixture
+    ///
+    /// The hash is the one this crate computed when the test was written, not
+    /// one produced by Bitcoin Core, so it only catches changes to the
+    /// serialization and not a wrong `hash_serialized_3`.
+    fn info() -> DumpInfo {
+        DumpInfo {
+            coins_written: 100,
+            base_hash: dump().block_hash,
+            base_height: 100,
+            txoutset_hash: "158dda4cda7563254df2291fae0dc44b205206d52443114f551decc9a7df5ce6"
+                .parse()
+                .unwrap(),
+            nchaintx: Some(101),
+        }
+    }
+
+    #[test]
+    fn txoutset_hash_regression() {
+        let report = validate(dump(), &info()).unwrap();
+        assert!(report.is_valid(), "{:?}", report.mismatches);
+        assert_eq!(report.coins, 100);
+        assert_eq!(report.max_height, 100);
+
+        // The legacy encoding of the same coins hashes the same
+        let legacy = Dump::from_reader(Cursor::new(DUMP_27_0), ComputeAddresses::No).unwrap();
+        let report = validate(legacy, &info()).unwrap();
+        assert!(report.is_valid(), "{:?}", report.mismatches);
+    }
+
+    #[test]
+    fn report_each_mismatch() {
+        let mut info = info();
+        info.coins_written = 99;
+        info.base_hash = BlockHash::all_zeros();
+        info.base_height = 101;
+        info.txoutset_hash = sha256d::Hash::all_zeros();
+
+        let report = validate(dump(), &info).unwrap();
+        assert_eq!(
+            report.mismatches,
+            [
+                Mismatch::BaseHash {
+                    expected: BlockHash::all_zeros(),
+                    found: dump().block_hash,
+                },
+                Mismatch::HeaderCount {
+                    expected: 99,
+                    found: 100,
+                },
+                Mismatch::CoinCount {
+                    expected: 99,
+                    found: 100,
+                },
+                Mismatch::TxoutsetHash {
+                    expected: sha256d::Hash::all_zeros(),
+                    found: report.txoutset_hash,
+                },
+                Mismatch::MaxHeight {
+                    expected: 101,
+                    found: 100,
+                },
+            ]
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn parse_rpc_result() {
+        let expected = info();
+        let json = format!(
+            r#"{{
+  "coins_written": 100,
+  "base_hash": "{}",
+  "base_height": 100,
+  "path": "/tmp/utxo.dat",
+  "txoutset_hash": "{}",
+  "nchaintx": 101
+}}"#,
+            expected.base_hash, expected.txoutset_hash
+        );
+
+        assert_eq!(DumpInfo::from_json(json.as_bytes()).unwrap(), expected);
+    }
+}
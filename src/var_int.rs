@@ -1,3 +1,4 @@
+use bitcoin::consensus::encode::Error;
 use bitcoin::consensus::{Decodable, Encodable, ReadExt};
 
 /// Variable-length Integers
@@ -28,54 +29,106 @@ use bitcoin::consensus::{Decodable, Encodable, ReadExt};
 pub struct VarInt(u64);
 
 impl VarInt {
+    /// Largest encoded size of a `u64`
+    pub const MAX_SIZE: usize = (u64::BITS as usize).div_ceil(7);
+
     pub fn new(value: u64) -> Self {
         Self(value)
     }
-}
 
-impl Encodable for VarInt {
-    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
-        &self,
-        writer: &mut W,
-    ) -> Result<usize, bitcoin::io::Error> {
+    /// Encode into the start of `out`, returning the number of bytes written
+    ///
+    /// Returns `None` if `out` is too short for the encoding.
+    pub fn encode_to_slice(&self, out: &mut [u8]) -> Option<usize> {
+        let mut tmp = [0; Self::MAX_SIZE];
+        let len = self.encode_reversed(&mut tmp);
+        let out = out.get_mut(..len)?;
+        for (o, b) in out.iter_mut().zip(tmp[..len].iter().rev()) {
+            *o = *b;
+        }
+
+        Some(len)
+    }
+
+    /// Decode from the start of `bytes`, returning the value and the number of
+    /// bytes consumed
+    pub fn decode_from_slice(bytes: &[u8]) -> Result<(Self, usize), Error> {
+        let mut n: u64 = 0;
+
+        for (i, b) in bytes.iter().enumerate() {
+            match Self::step(n, *b)? {
+                Step::More(next) => n = next,
+                Step::Done(value) => return Ok((Self(value), i + 1)),
+            }
+        }
+
+        Err(bitcoin::io::Error::from(bitcoin::io::ErrorKind::UnexpectedEof).into())
+    }
+
+    /// Write the digits least significant first, returning the length
+    fn encode_reversed(&self, bytes: &mut [u8; Self::MAX_SIZE]) -> usize {
         let mut num = self.0;
-        let mut bytes = Vec::with_capacity((std::mem::size_of::<u64>() * 8).div_ceil(7));
+        let mut len = 0;
 
-        let mut first = true;
         loop {
-            let tmp = (num & 0x7f) | if first { 0x00 } else { 0x80 };
-            bytes.push(tmp as u8);
+            let tmp = (num & 0x7f) | if len == 0 { 0x00 } else { 0x80 };
+            bytes[len] = tmp as u8;
+            len += 1;
             if num <= 0x7f {
-                break;
+                return len;
             }
             num = (num >> 7) - 1;
-            first = false;
         }
+    }
+
+    /// Accumulate one encoded byte into the partial value `n`
+    fn step(n: u64, b: u8) -> Result<Step, Error> {
+        let b = u64::from(b);
+        if n > u64::MAX >> 7 {
+            return Err(Error::NonMinimalVarInt);
+        }
+        let n = (n << 7) | (b & 0x7f);
+        if (b & 0x80) != 0 {
+            if n == u64::MAX {
+                return Err(Error::NonMinimalVarInt);
+            }
+            Ok(Step::More(n + 1))
+        } else {
+            Ok(Step::Done(n))
+        }
+    }
+}
+
+/// Progress of decoding a single byte
+enum Step {
+    /// Another byte follows
+    More(u64),
+    /// The final value
+    Done(u64),
+}
 
-        let bytes: Vec<u8> = bytes.into_iter().rev().collect();
-        writer.write(bytes.as_slice())
+impl Encodable for VarInt {
+    fn consensus_encode<W: bitcoin::io::Write + ?Sized>(
+        &self,
+        writer: &mut W,
+    ) -> Result<usize, bitcoin::io::Error> {
+        let mut bytes = [0; Self::MAX_SIZE];
+        let len = self.encode_reversed(&mut bytes);
+        bytes[..len].reverse();
+        writer.write_all(&bytes[..len])?;
+
+        Ok(len)
     }
 }
 
 impl Decodable for VarInt {
-    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(
-        reader: &mut R,
-    ) -> Result<Self, bitcoin::consensus::encode::Error> {
+    fn consensus_decode<R: bitcoin::io::Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
         let mut n: u64 = 0;
 
         loop {
-            let b = reader.read_u8()? as u64;
-            if n > u64::MAX >> 7 {
-                return Err(bitcoin::consensus::encode::Error::NonMinimalVarInt);
-            }
-            n = (n << 7) | (b & 0x7f);
-            if (b & 0x80) != 0 {
-                if n == u64::MAX {
-                    return Err(bitcoin::consensus::encode::Error::NonMinimalVarInt);
-                }
-                n += 1;
-            } else {
-                return Ok(Self(n));
+            match Self::step(n, reader.read_u8()?)? {
+                Step::More(next) => n = next,
+                Step::Done(value) => return Ok(Self(value)),
             }
         }
     }
@@ -140,6 +193,25 @@ mod test {
             let mut encoded = bytes.as_slice();
             let var_int = VarInt::consensus_decode(&mut encoded).expect("decode");
             assert_eq!(var_int, VarInt(num), "decode {:?} -> {}", bytes, num);
+
+            let mut buf = [0; VarInt::MAX_SIZE];
+            let len = VarInt(num).encode_to_slice(&mut buf).expect("encode");
+            assert_eq!(&buf[..len], bytes);
+            let decoded = VarInt::decode_from_slice(&bytes).expect("decode");
+            assert_eq!(decoded, (VarInt(num), bytes.len()));
         }
     }
+
+    #[test]
+    fn slice_errors() {
+        assert!(VarInt::decode_from_slice(&[]).is_err());
+        assert!(VarInt::decode_from_slice(&[0x80]).is_err());
+        assert!(VarInt::decode_from_slice(&[0xff; 11]).is_err());
+        assert_eq!(VarInt(1 << 32).encode_to_slice(&mut [0; 4]), None);
+
+        let max = VarInt(u64::MAX);
+        let mut buf = [0; VarInt::MAX_SIZE];
+        let len = max.encode_to_slice(&mut buf).expect("encode");
+        assert_eq!(VarInt::decode_from_slice(&buf[..len]).unwrap(), (max, len));
+    }
 }
@@ -0,0 +1,318 @@
+//! Finding the coins of a large set of scripts
+//!
+//! A [`WatchList`] keys each script by its compressed form, as stored in a
+//! dump: the 20-byte hash of P2PKH and P2SH scripts, the full x-coordinate
+//! of P2PK keys, and the SHA256 of any other script. Entries are matched
+//! before their scripts are decompressed, so non-matching entries never build
+//! a `ScriptBuf` or `Address`.
+
+use std::collections::{HashMap, HashSet};
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::io::Read;
+use bitcoin::{Address, PubkeyHash, ScriptBuf, ScriptHash};
+
+use crate::script::{self, MAX_SCRIPT_SIZE};
+use crate::{Amount, Dump, Error, TxOut, VarInt};
+
+/// Compression code of the scripts stored verbatim
+const RAW: u8 = 0x06;
+
+/// Compression code followed by up to 32 bytes identifying the script
+type Key = [u8; 33];
+
+/// A set of scripts to look for in a dump
+///
+/// Each entry takes 33 bytes regardless of the script size.
+#[derive(Debug, Clone, Default)]
+pub struct WatchList {
+    keys: HashSet<Key>,
+}
+
+impl WatchList {
+    /// An empty watch list
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An empty watch list with room for `capacity` entries
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            keys: HashSet::with_capacity(capacity),
+        }
+    }
+
+    /// Number of entries
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// Whether there are no entries
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Watch a script public key, returning whether it was newly added
+    ///
+    /// Scripts longer than 10,000 bytes are unspendable, never appear in a
+    /// dump and are not added.
+    pub fn insert_script(&mut self, script: &bitcoin::Script) -> bool {
+        script_key(script).is_some_and(|key| self.keys.insert(key))
+    }
+
+    /// Watch the script public key of an address
+    pub fn insert_address(&mut self, address: &Address) -> bool {
+        self.insert_script(&address.script_pubkey())
+    }
+
+    /// Watch the P2PKH script paying to `hash`
+    pub fn insert_pubkey_hash(&mut self, hash: PubkeyHash) -> bool {
+        self.keys.insert(hash_key(0x00, hash.as_byte_array()))
+    }
+
+    /// Watch the P2SH script paying to `hash`
+    pub fn insert_script_hash(&mut self, hash: ScriptHash) -> bool {
+        self.keys.insert(hash_key(0x01, hash.as_byte_array()))
+    }
+
+    /// Whether `script` is watched
+    pub fn contains(&self, script: &bitcoin::Script) -> bool {
+        script_key(script).is_some_and(|key| self.keys.contains(&key))
+    }
+
+    /// Iterate over the entries of `dump` paying to watched scripts
+    pub fn matches<R>(&self, dump: Dump<R>) -> Matches<'_, R>
+    where
+        R: Read,
+    {
+        Matches {
+            dump,
+            error: None,
+            watch_list: self,
+        }
+    }
+
+    /// Collect the entries of `dump` paying to watched scripts and the
+    /// balance of each script
+    pub fn scan<R>(&self, dump: Dump<R>) -> Result<Scan, Error>
+    where
+        R: Read,
+    {
+        let mut scan = Scan::default();
+        let mut matches = self.matches(dump);
+        while let Some(tx_out) = matches.try_next()? {
+            let balance = scan
+                .balances
+                .entry(tx_out.script_pubkey.clone())
+                .or_insert(Amount::ZERO);
+            // The dump rejects totals beyond MAX_MONEY
+            *balance = balance
+                .checked_add_money(tx_out.amount)
+                .expect("balances stay within MAX_MONEY");
+            scan.tx_outs.push(tx_out);
+        }
+
+        Ok(scan)
+    }
+}
+
+impl FromIterator<ScriptBuf> for WatchList {
+    fn from_iter<T: IntoIterator<Item = ScriptBuf>>(iter: T) -> Self {
+        let mut watch_list = Self::new();
+        watch_list.extend(iter);
+        watch_list
+    }
+}
+
+impl Extend<ScriptBuf> for WatchList {
+    fn extend<T: IntoIterator<Item = ScriptBuf>>(&mut self, iter: T) {
+        for script in iter {
+            self.insert_script(&script);
+        }
+    }
+}
+
+/// The result of [`WatchList::scan`]
+#[derive(Debug, Clone, Default)]
+pub struct Scan {
+    /// Matching entries in dump order
+    pub tx_outs: Vec<TxOut>,
+    /// Total amount held by each watched script with at least one entry
+    pub balances: HashMap<ScriptBuf, Amount>,
+}
+
+/// Iterator over the entries of a dump paying to watched scripts
+pub struct Matches<'a, R>
+where
+    R: Read,
+{
+    /// The dump being searched
+    dump: Dump<R>,
+    /// First error encountered while iterating
+    error: Option<Error>,
+    /// The scripts to look for
+    watch_list: &'a WatchList,
+}
+
+impl<R> Matches<'_, R>
+where
+    R: Read,
+{
+    /// The error that stopped iteration, if any
+    pub fn error(&self) -> Option<&Error> {
+        self.error.as_ref()
+    }
+
+    /// Find the next matching entry, distinguishing the end of the dump from
+    /// errors
+    pub fn try_next(&mut self) -> Result<Option<TxOut>, Error> {
+        let keys = &self.watch_list.keys;
+        let mut tx_out = TxOut::default();
+        let found = self.dump.read_into_if(&mut tx_out, &mut |compressed| {
+            compressed_key(compressed).is_some_and(|key| keys.contains(&key))
+        })?;
+
+        Ok(found.then_some(tx_out))
+    }
+
+    /// Give back the dump, positioned after the last entry read
+    pub fn into_inner(self) -> Dump<R> {
+        self.dump
+    }
+}
+
+impl<R> Iterator for Matches<'_, R>
+where
+    R: Read,
+{
+    type Item = TxOut;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+        match self.try_next() {
+            Ok(item) => item,
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+/// Key from a compression code and a hash or key x-coordinate
+fn hash_key(code: u8, hash: &[u8]) -> Key {
+    let mut key = [0; 33];
+    key[0] = code;
+    key[1..=hash.len()].copy_from_slice(hash);
+    key
+}
+
+/// Key of a script public key
+fn script_key(script: &bitcoin::Script) -> Option<Key> {
+    let mut compressed = Vec::new();
+    script::compress(script, &mut compressed).expect("vectors do not fail");
+    compressed_key(&compressed)
+}
+
+/// Key of the compressed script at the start of `bytes`
+///
+/// Oversized scripts have no key.
+fn compressed_key(bytes: &[u8]) -> Option<Key> {
+    let (size, header_len) = VarInt::decode_from_slice(bytes).ok()?;
+    let payload = &bytes[header_len..];
+    match u64::from(size) {
+        code @ 0x00..=0x01 => Some(hash_key(code as u8, payload.get(..20)?)),
+        // Bitcoin Core compresses any push of this form without checking the
+        // key, so all of the x-coordinate is needed to tell keys apart
+        code @ 0x02..=0x05 => Some(hash_key(code as u8, payload.get(..32)?)),
+        size => {
+            let len = usize::try_from(size - u64::from(RAW)).ok()?;
+            if len > MAX_SCRIPT_SIZE {
+                return None;
+            }
+            let hash = sha256::Hash::hash(payload.get(..len)?);
+            Some(hash_key(RAW, hash.as_byte_array()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::test::DUMP_28_0;
+    use crate::ComputeAddresses;
+
+    fn dump() -> Dump<Cursor<&'static [u8]>> {
+        Dump::from_reader(Cursor::new(DUMP_28_0), ComputeAddresses::No).unwrap()
+    }
+
+    #[test]
+    fn match_watched_scripts() {
+        let all: Vec<_> = dump().collect();
+        let watched = [&all[3], &all[40], &all[99]];
+
+        let mut watch_list: WatchList = watched
+            .iter()
+            .map(|tx_out| tx_out.script_pubkey.clone())
+            .collect();
+        watch_list.insert_pubkey_hash(PubkeyHash::all_zeros());
+        watch_list.insert_script(&ScriptBuf::new_p2wpkh(&bitcoin::WPubkeyHash::all_zeros()));
+        assert!(watch_list.contains(&all[40].script_pubkey));
+
+        let scan = watch_list.scan(dump()).unwrap();
+        let expected: Vec<_> = all
+            .iter()
+            .filter(|tx_out| {
+                watched
+                    .iter()
+                    .any(|watched| watched.script_pubkey == tx_out.script_pubkey)
+            })
+            .cloned()
+            .collect();
+        assert_eq!(expected.len(), 3);
+        assert_eq!(scan.tx_outs, expected);
+
+        for (script, balance) in &scan.balances {
+            let total = expected
+                .iter()
+                .filter(|tx_out| &tx_out.script_pubkey == script)
+                .map(|tx_out| tx_out.amount.to_sat())
+                .sum::<u64>();
+            assert_eq!(balance.to_sat(), total);
+        }
+    }
+
+    #[test]
+    fn keys_of_p2pk_scripts() {
+        let key: bitcoin::PublicKey =
+            "0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798"
+                .parse()
+                .unwrap();
+        let p2pk = ScriptBuf::new_p2pk(&key);
+        let mut watch_list = WatchList::new();
+        assert!(watch_list.insert_script(&p2pk));
+        assert!(watch_list.contains(&p2pk));
+
+        // Same first 20 bytes of the x-coordinate
+        let mut forged = p2pk.to_bytes();
+        forged[30] ^= 1;
+        assert!(!watch_list.contains(&ScriptBuf::from_bytes(forged)));
+    }
+
+    #[test]
+    fn keys_of_special_scripts() {
+        let hash = PubkeyHash::from_byte_array([7; 20]);
+        let mut watch_list = WatchList::new();
+        assert!(watch_list.insert_pubkey_hash(hash));
+        assert!(!watch_list.insert_script(&ScriptBuf::new_p2pkh(&hash)));
+        assert!(!watch_list.contains(&ScriptBuf::new_p2sh(&ScriptHash::from_byte_array([7; 20]))));
+
+        let oversized = ScriptBuf::from_bytes(vec![0; MAX_SCRIPT_SIZE + 1]);
+        assert!(!watch_list.insert_script(&oversized));
+        assert_eq!(watch_list.len(), 1);
+    }
+}